@@ -1,15 +1,16 @@
 /// CLI for rust-cktap
 use clap::{Parser, Subcommand};
-use rpassword::read_password;
-use rust_cktap::commands::{CkTransport, Read};
+use rust_cktap::bitcoin::hex::{DisplayHex, FromHex};
+use rust_cktap::commands::{CkTransport, Nfc, Read};
 #[cfg(feature = "emulator")]
 use rust_cktap::emulator;
 #[cfg(not(feature = "emulator"))]
 use rust_cktap::pcsc;
-use rust_cktap::secp256k1::rand;
-use rust_cktap::{apdu::Error, commands::Certificate, rand_chaincode, CkTapCard};
-use std::io;
-use std::io::Write;
+use rust_cktap::secp256k1::{ecdh::SharedSecret, rand};
+use rust_cktap::{
+    apdu::Error, commands::Certificate, harden, rand_chaincode, read_cvc_secure, CkTapCard,
+};
+use serde_json::json;
 
 /// SatsCard CLI
 #[derive(Parser)]
@@ -25,10 +26,14 @@ struct SatsCardCli {
 enum SatsCardCommand {
     /// Show the card status
     Debug,
+    /// Print the non-secret card status as JSON
+    Status,
     /// Show current deposit address
     Address,
-    /// Check this card was made by Coinkite: Verifies a certificate chain up to root factory key.
+    /// Show the card's certificate chain, from the card key up to the root factory key
     Certs,
+    /// Check this card was made by Coinkite: Verifies a certificate chain up to root factory key.
+    Verify,
     /// Read the pubkey
     Read,
     /// Pick a new private key and start a fresh slot. Current slot must be unsealed.
@@ -37,6 +42,8 @@ enum SatsCardCommand {
     Unseal,
     /// Get the payment address and verify it follows from the chain code and master public key
     Derive,
+    /// Show the url a phone would open when tapping the card
+    Nfc,
 }
 
 /// TapSigner CLI
@@ -53,10 +60,20 @@ struct TapSignerCli {
 enum TapSignerCommand {
     /// Show the card status
     Debug,
-    /// Check this card was made by Coinkite: Verifies a certificate chain up to root factory key.
+    /// Print the non-secret card status as JSON
+    Status,
+    /// Show the card's certificate chain, from the card key up to the root factory key
     Certs,
+    /// Check this card was made by Coinkite: Verifies a certificate chain up to root factory key.
+    Verify,
     /// Read the pubkey (requires CVC)
     Read,
+    /// Show a receive address at the current derivation path
+    Address {
+        /// address index, for the address at <path>/0/<index>
+        #[clap(short, long, default_value = "0")]
+        index: u32,
+    },
     /// This command is used once to initialize a new card.
     Init,
     /// Derive a public key at the given hardened path
@@ -65,6 +82,26 @@ enum TapSignerCommand {
         #[clap(short, long, value_delimiter = ',', num_args = 1..)]
         path: Vec<u32>,
     },
+    /// Get the xpub at the current derivation path
+    Xpub {
+        /// give the master (`m`) xpub instead
+        #[clap(short, long)]
+        master: bool,
+    },
+    /// Show the url a phone would open when tapping the card
+    Nfc,
+    /// Change the CVC
+    Change,
+    /// Get the encrypted backup of the master private key
+    Backup,
+    /// Sign a 32 byte digest, printing the signature as JSON
+    Sign {
+        /// hex encoded digest
+        digest: String,
+        /// unhardened subpath from the current derivation path, eg. for 0/1 use 0,1
+        #[clap(short, long, value_delimiter = ',', num_args = 1..)]
+        subpath: Option<Vec<u32>>,
+    },
 }
 
 fn main() -> Result<(), Error> {
@@ -77,6 +114,7 @@ fn main() -> Result<(), Error> {
     let mut card = emulator::find_emulator()?;
 
     let rng = &mut rand::thread_rng();
+    let snapshot = card.snapshot();
 
     match &mut card {
         CkTapCard::SatsCard(sc) => {
@@ -85,22 +123,35 @@ fn main() -> Result<(), Error> {
                 SatsCardCommand::Debug => {
                     dbg!(&sc);
                 }
-                SatsCardCommand::Address => println!("Address: {}", sc.address().unwrap()),
-                SatsCardCommand::Certs => check_cert(sc),
-                SatsCardCommand::Read => read(sc, None),
+                SatsCardCommand::Status => print_json(&snapshot),
+                SatsCardCommand::Address => {
+                    let address = sc.address()?;
+                    print_json(&json!({
+                        "slot": sc.active_slot().index(),
+                        "address": address.to_string(),
+                    }));
+                }
+                SatsCardCommand::Certs => cert_chain(sc)?,
+                SatsCardCommand::Verify => verify(sc),
+                SatsCardCommand::Nfc => print_json(&json!({ "url": sc.nfc()?.to_string() })),
+                SatsCardCommand::Read => read(sc, None)?,
                 SatsCardCommand::New => {
-                    let slot = sc.slot().expect("current slot number");
+                    let slot = sc.next_slot()?;
                     let chain_code = Some(rand_chaincode(rng).to_vec());
-                    let response = &sc.new_slot(slot, chain_code, cvc()).unwrap();
-                    println!("{}", response)
+                    let response = sc.new_slot(slot, chain_code, cvc()?)?;
+                    print_json(&json!({ "slot": response.slot }));
                 }
                 SatsCardCommand::Unseal => {
-                    let slot = sc.slot().expect("current slot number");
-                    let response = &sc.unseal(slot, cvc()).unwrap();
-                    println!("{}", response)
+                    let response = sc.unseal(sc.active_slot(), cvc()?)?;
+                    print_json(&json!({
+                        "slot": response.slot,
+                        "pubkey": response.pubkey()?.to_string(),
+                        "privkey": response.privkey()?.display_secret().to_string(),
+                    }));
                 }
                 SatsCardCommand::Derive => {
-                    dbg!(&sc.derive());
+                    let address = sc.derive_address()?;
+                    print_json(&json!({ "address": address.to_string() }));
                 }
             }
         }
@@ -110,15 +161,65 @@ fn main() -> Result<(), Error> {
                 TapSignerCommand::Debug => {
                     dbg!(&ts);
                 }
-                TapSignerCommand::Certs => check_cert(ts),
-                TapSignerCommand::Read => read(ts, Some(cvc())),
+                TapSignerCommand::Status => print_json(&snapshot),
+                TapSignerCommand::Certs => cert_chain(ts)?,
+                TapSignerCommand::Verify => verify(ts),
+                TapSignerCommand::Nfc => print_json(&json!({ "url": ts.nfc()?.to_string() })),
+                TapSignerCommand::Read => read(ts, Some(cvc()?))?,
+                TapSignerCommand::Address { index } => {
+                    let address = ts.address(index, cvc()?)?;
+                    let path = ts.derivation_path()?.map(|path| path.to_string());
+                    print_json(&json!({
+                        "path": path.map(|path| format!("{}/0/{}", path, index)),
+                        "address": address.to_string(),
+                    }));
+                }
                 TapSignerCommand::Init => {
                     let chain_code = rand_chaincode(rng).to_vec();
-                    let response = &ts.init(chain_code, cvc());
-                    dbg!(response);
+                    let response = ts.init(chain_code, cvc()?)?;
+                    print_json(&json!({
+                        "pubkey": response.pubkey.to_string(),
+                        "path": response.path.to_string(),
+                    }));
                 }
                 TapSignerCommand::Derive { path } => {
-                    dbg!(&ts.derive(path, cvc()));
+                    // set most significant bit to 1 to represent hardened path steps
+                    let path = path
+                        .into_iter()
+                        .map(harden)
+                        .collect::<Result<Vec<u32>, Error>>()?;
+                    let response = ts.derive(path, cvc()?)?;
+                    print_json(&json!({
+                        "master_pubkey": response.master_pubkey.to_lower_hex_string(),
+                        "pubkey": response.pubkey.map(|pubkey| pubkey.to_lower_hex_string()),
+                        "chain_code": response.chain_code.to_lower_hex_string(),
+                    }));
+                }
+                TapSignerCommand::Xpub { master } => {
+                    let xpub = ts.xpub(master, cvc()?)?;
+                    print_json(&json!({ "xpub": xpub.to_string() }));
+                }
+                TapSignerCommand::Change => {
+                    println!("Current CVC");
+                    let old_cvc = cvc()?;
+                    println!("New CVC");
+                    let response = ts.change_cvc(old_cvc, cvc()?)?;
+                    print_json(&json!({ "success": response.success }));
+                }
+                TapSignerCommand::Backup => {
+                    let backup = ts.backup(cvc()?)?;
+                    print_json(&json!({ "backup": backup.to_lower_hex_string() }));
+                }
+                TapSignerCommand::Sign { digest, subpath } => {
+                    let digest = Vec::<u8>::from_hex(&digest).map_err(|e| {
+                        Error::InvalidDigest(format!("Digest must be hex encoded: {}", e))
+                    })?;
+                    let response = ts.sign(digest, subpath, cvc()?)?;
+                    print_json(&json!({
+                        "slot": response.slot,
+                        "sig": response.sig.to_lower_hex_string(),
+                        "pubkey": response.pubkey.to_lower_hex_string(),
+                    }));
                 }
             }
         }
@@ -129,30 +230,48 @@ fn main() -> Result<(), Error> {
 
 // handler functions for each command
 
-fn check_cert<T: CkTransport>(card: &mut dyn Certificate<T>) {
-    if let Ok(k) = card.check_certificate() {
-        println!(
-            "Genuine card from Coinkite.\nHas cert signed by: {}",
-            k.name()
-        )
-    } else {
-        println!("Card failed to verify. Not a genuine card")
-    }
+fn cert_chain<T: CkTransport>(card: &mut dyn Certificate<T>) -> Result<(), Error> {
+    let chain = card.cert_chain()?;
+    let keys: Vec<String> = chain.keys().iter().map(|key| key.to_string()).collect();
+    print_json(&json!({ "chain": keys }));
+    Ok(())
 }
 
-fn read<T: CkTransport>(card: &mut dyn Read<T>, cvc: Option<String>) {
-    match card.read(cvc) {
-        Ok(resp) => println!("{}", resp),
-        Err(e) => {
-            dbg!(&e);
-            println!("Failed to read with error: ")
-        }
+fn verify<T: CkTransport>(card: &mut dyn Certificate<T>) {
+    match card.check_certificate() {
+        Ok(root) => print_json(&json!({
+            "genuine": true,
+            "root": root.name(),
+            "root_key": root.pubkey().to_string(),
+        })),
+        Err(e) => print_json(&json!({
+            "genuine": false,
+            "error": e.to_string(),
+        })),
     }
 }
 
-fn cvc() -> String {
-    print!("Enter cvc: ");
-    io::stdout().flush().unwrap();
-    let cvc = read_password().unwrap();
-    cvc.trim().to_string()
+fn read<T: CkTransport>(card: &mut dyn Read<T>, cvc: Option<String>) -> Result<(), Error> {
+    let response = card.read(cvc)?;
+    // a TAPSIGNER encrypts the pubkey with the session key
+    let session_key = match card.requires_auth() {
+        true => card
+            .session_keys()
+            .map(|keys| SharedSecret::from_bytes(*keys.session_key())),
+        false => None,
+    };
+    print_json(&json!({
+        "pubkey": response.pubkey(session_key)?.to_string(),
+        "sig": response.sig.to_lower_hex_string(),
+    }));
+    Ok(())
+}
+
+fn print_json<S: serde::Serialize>(value: &S) {
+    println!("{}", serde_json::to_string_pretty(value).unwrap());
+}
+
+fn cvc() -> Result<String, Error> {
+    read_cvc_secure("Enter cvc: ")
+        .map_err(|e| Error::InvalidCvc(format!("Failed to read CVC: {}", e)))
 }
@@ -0,0 +1,71 @@
+// Example Android JNI transport using `android.nfc.tech.IsoDep`
+//
+// Build as a cdylib for an Android target and load it from the app:
+//
+//     package com.example
+//
+//     class CkTap {
+//         companion object { init { System.loadLibrary("android_jni") } }
+//         external fun describe(isoDep: IsoDep): String
+//     }
+//
+// The app connects the `IsoDep` tag it got from the NFC dispatch before calling `describe`, APDUs
+// are then exchanged with `IsoDep.transceive`.
+
+use jni::objects::{JByteArray, JObject, JValue};
+use jni::sys::jstring;
+use jni::JNIEnv;
+use rust_cktap::apdu::{strip_status_word, Error};
+use rust_cktap::commands::{CkTransport, FnTransport};
+use rust_cktap::CkTapCard;
+
+/// Exchange one APDU with `IsoDep.transceive`. The byte arrays are local refs, they are created in
+/// their own local frame and freed when it is popped, else a long flow like `dump_all_slots`
+/// could overflow the local reference table of the native call.
+fn transceive(env: &mut JNIEnv, iso_dep: &JObject, command_apdu: &[u8]) -> Result<Vec<u8>, Error> {
+    env.with_local_frame(2, |env| {
+        let command = env.byte_array_from_slice(command_apdu)?;
+        let response = env
+            .call_method(iso_dep, "transceive", "([B)[B", &[JValue::Object(&command)])?
+            .l()?;
+        env.convert_byte_array(JByteArray::from(response))
+    })
+    .map_err(|e: jni::errors::Error| Error::Transport(e.to_string()))
+    .and_then(strip_status_word)
+}
+
+fn describe<T: CkTransport>(card: CkTapCard<T>) -> Result<String, Error> {
+    match card {
+        CkTapCard::SatsCard(mut sc) => {
+            let address = sc.address()?;
+            Ok(format!(
+                "SATSCARD slot {} of {}: {}",
+                sc.slots.0, sc.slots.1, address
+            ))
+        }
+        CkTapCard::TapSigner(ts) | CkTapCard::SatsChip(ts) => match ts.derivation_path()? {
+            Some(path) => Ok(format!("TAPSIGNER at {}", path)),
+            None => Ok("TAPSIGNER, not initialized".to_string()),
+        },
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_example_CkTap_describe<'local>(
+    env: JNIEnv<'local>,
+    _this: JObject<'local>,
+    iso_dep: JObject<'local>,
+) -> jstring {
+    // The transport calls back into Java while this frame still owns `env`.
+    let mut transport_env = unsafe { env.unsafe_clone() };
+    let transport = FnTransport::from_fn(|command_apdu: &[u8]| {
+        transceive(&mut transport_env, &iso_dep, command_apdu)
+    });
+    let description = transport
+        .to_cktap()
+        .and_then(describe)
+        .unwrap_or_else(|e| format!("error: {}", e));
+    env.new_string(description)
+        .map(|s| s.into_raw())
+        .unwrap_or(std::ptr::null_mut())
+}
@@ -2,39 +2,38 @@ extern crate core;
 
 use rust_cktap::apdu::Error;
 use rust_cktap::commands::{Certificate, Wait};
-use rust_cktap::{pcsc, rand_chaincode, CkTapCard};
+use rust_cktap::{pcsc, rand_chaincode, read_cvc_secure, CardLifecycle, CkTapCard};
 
 use secp256k1::rand;
-use std::io;
-use std::io::Write;
 
 fn get_cvc() -> String {
-    print!("Enter cvc: ");
-    io::stdout().flush().unwrap();
-    let mut cvc: String = String::new();
-    let _btye_count = std::io::stdin().read_line(&mut cvc).unwrap();
-    cvc.trim().to_string()
+    read_cvc_secure("Enter cvc: ").unwrap()
 }
 
 // Example using pcsc crate
 fn main() -> Result<(), Error> {
-    let card = pcsc::find_first()?;
-    dbg!(&card);
+    let mut card = pcsc::find_first()?;
+    println!("{}", card);
 
     let rng = &mut rand::thread_rng();
 
+    // if auth delay call wait
+    match &mut card {
+        CkTapCard::TapSigner(ts) | CkTapCard::SatsChip(ts) => ts.clear_auth_delay(|auth_delay| {
+            dbg!(auth_delay);
+        }),
+        CkTapCard::SatsCard(sc) => sc.clear_auth_delay(|auth_delay| {
+            dbg!(auth_delay);
+        }),
+    }?;
+
     match card {
-        CkTapCard::TapSigner(mut ts) => {
+        // a SATSCHIP takes the same commands as a TAPSIGNER
+        CkTapCard::TapSigner(mut ts) | CkTapCard::SatsChip(mut ts) => {
             let cvc: String = get_cvc();
 
-            // if auth delay call wait
-            while ts.auth_delay.is_some() {
-                dbg!(ts.auth_delay.unwrap());
-                ts.wait(None)?;
-            }
-
             // only do this once per card!
-            if ts.path.is_none() {
+            if ts.lifecycle() == CardLifecycle::Uninitialized {
                 let chain_code = rand_chaincode(rng).to_vec();
                 let new_result = ts.init(chain_code, cvc)?;
                 dbg!(new_result);
@@ -54,38 +53,9 @@ fn main() -> Result<(), Error> {
             // let nfc_result = card.nfc()?;
             // dbg!(nfc_result);
         }
-        CkTapCard::SatsChip(mut chip) => {
-            let _cvc: String = get_cvc();
-
-            // if auth delay call wait
-            while chip.auth_delay.is_some() {
-                dbg!(chip.auth_delay.unwrap());
-                chip.wait(None)?;
-            }
-
-            // only do this once per card!
-            if chip.path.is_none() {
-                let chain_code = rand_chaincode(rng).to_vec();
-                let new_result = chip.init(chain_code, get_cvc())?;
-                dbg!(new_result);
-            }
-
-            // let read_result = card.read(cvc)?;
-            // dbg!(read_result);
-
-            // let nfc_result = card.nfc()?;
-            // dbg!(nfc_result);
-        }
         CkTapCard::SatsCard(mut sc) => {
-            // if auth delay call wait
-            while sc.auth_delay.is_some() {
-                dbg!(sc.auth_delay.unwrap());
-                let wait_response = sc.wait(None)?;
-                dbg!(wait_response);
-            }
-
-            // let read_result = sc.read(None)?;
-            // dbg!(read_result);
+            let read_result = sc.read_slot()?;
+            println!("Slot {}: {}", read_result.slot, read_result.address);
 
             // let derive_result = card.derive()?;
             // dbg!(&derive_result);
@@ -95,24 +65,23 @@ fn main() -> Result<(), Error> {
             // let nfc_result = card.nfc()?;
             // dbg!(nfc_result);
 
-            // if let Some(slot) = card.slots.first() {
-            //     if slot == &0 {
-            //         // TODO must unseal first
-            //         let chain_code = rand_chaincode(rng).to_vec();
-            //         let new_result = card.new_slot(0, chain_code, get_cvc())?;
-            //     }
-            // }
+            // current slot must be unsealed before a new slot can be picked
+            // let slot = sc.active_slot();
+            // let unseal_result = sc.unseal(slot, get_cvc())?;
+            // let chain_code = Some(rand_chaincode(rng).to_vec());
+            // let next_slot = sc.next_slot()?;
+            // let new_result = sc.new_slot(next_slot, chain_code, get_cvc())?;
 
             // let certs_result = card.certs()?;
             // dbg!(certs_result);
 
-            // let unseal_result = card.unseal(0, get_cvc())?;
+            // let unseal_result = card.unseal(card.active_slot(), get_cvc())?;
             // dbg!(unseal_result);
 
-            // let dump_result = card.dump(0, None)?;
+            // let dump_result = card.dump(card.slot_at(0)?, None)?;
             // dbg!(dump_result);
 
-            // let dump_result = card.dump(0, Some(get_cvc()))?;
+            // let dump_result = card.dump(card.slot_at(0)?, Some(get_cvc()))?;
             // dbg!(dump_result);
         }
     }
@@ -0,0 +1,46 @@
+extern crate core;
+
+use rust_cktap::apdu::Error;
+use rust_cktap::async_transport::AsyncCard;
+use rust_cktap::commands::{Certificate, Wait};
+use rust_cktap::{pcsc, CkTapCard};
+
+// Example using pcsc crate from async code
+fn main() -> Result<(), Error> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .expect("tokio runtime");
+    runtime.block_on(run())
+}
+
+async fn run() -> Result<(), Error> {
+    // pcsc calls block, run them on the blocking thread pool
+    let card = tokio::task::spawn_blocking(pcsc::find_first)
+        .await
+        .map_err(|e| Error::Tokio(e.to_string()))??;
+    println!("{}", card);
+
+    let mut card = AsyncCard::new(card);
+
+    // if auth delay call wait
+    card.run(|card| match card {
+        CkTapCard::TapSigner(ts) | CkTapCard::SatsChip(ts) => ts.clear_auth_delay(|auth_delay| {
+            dbg!(auth_delay);
+        }),
+        CkTapCard::SatsCard(sc) => sc.clear_auth_delay(|auth_delay| {
+            dbg!(auth_delay);
+        }),
+    })
+    .await?;
+
+    let name = card
+        .run(|card| match card {
+            CkTapCard::TapSigner(ts) | CkTapCard::SatsChip(ts) => ts.check_certificate(),
+            CkTapCard::SatsCard(sc) => sc.check_certificate(),
+        })
+        .await?
+        .name();
+    dbg!(name);
+
+    Ok(())
+}
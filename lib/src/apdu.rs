@@ -1,25 +1,51 @@
+use alloc::borrow::ToOwned;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use bitcoin::bip32::{ChildNumber, DerivationPath, Xpub};
+#[cfg(feature = "std")]
+use bitcoin::Network;
 /// An Application Protocol Data Unit (APDU) is the unit of communication between a smart card
 /// reader and a smart card. This file defines the Coinkite APDU and set of command/responses.
 use ciborium::de::from_reader;
 use ciborium::ser::into_writer;
 use ciborium::value::Value;
+use core::fmt;
+use core::fmt::{Debug, Formatter};
+use hex::{DisplayHex, FromHex};
 use secp256k1::ecdh::SharedSecret;
-use secp256k1::ecdsa::Signature;
-use secp256k1::hashes::hex::ToHex;
-use secp256k1::{PublicKey, SecretKey, XOnlyPublicKey};
+use secp256k1::ecdsa::{RecoverableSignature, RecoveryId, Signature};
+use secp256k1::hashes::sha256;
+use secp256k1::{Message, PublicKey, Secp256k1, SecretKey};
 use serde;
 use serde::{Deserialize, Serialize};
-use std::fmt;
-use std::fmt::{Debug, Formatter};
+use serde_bytes::ByteBuf;
+use subtle::ConstantTimeEq;
+use zeroize::Zeroize;
 
 pub const APP_ID: [u8; 15] = *b"\xf0CoinkiteCARDv1";
 pub const SELECT_CLA_INS_P1P2: [u8; 4] = [0x00, 0xA4, 0x04, 0x00];
 pub const CBOR_CLA_INS_P1P2: [u8; 4] = [0x00, 0xCB, 0x00, 0x00];
 
+/// Newest cktap protocol version this crate understands, from the status `proto` field.
+pub const SUPPORTED_PROTO: usize = 1;
+
 // require nonce sizes (bytes)
 pub const CARD_NONCE_SIZE: usize = 16;
 pub const USER_NONCE_SIZE: usize = 16;
 
+// response field sizes (bytes)
+const PUBKEY_SIZE: usize = 33;
+const SIG_SIZE: usize = 64;
+const KEY_SIZE: usize = 32;
+const CHAIN_CODE_SIZE: usize = 32;
+const XPUB_SIZE: usize = 78;
+
+/// Shown in place of CVCs and private keys in `Debug` output.
+const REDACTED: &str = "<redacted>";
+
 // Errors
 #[derive(Debug)]
 pub enum Error {
@@ -30,13 +56,206 @@ pub enum Error {
         code: usize,
     },
     IncorrectSignature(String),
+    BadSignature(String),
+    InvalidDigest(String),
+    InvalidPath(String),
+    InvalidSubpath(String),
+    UnsupportedInputType(String),
+    InvalidSlot(String),
+    NonceReuse(String),
+    InvalidNonce(String),
+    InvalidChainCode(String),
+    InvalidPublicKey(String),
+    InvalidPrivateKey(String),
+    InvalidAddress(String),
+    InvalidXpub(String),
+    InvalidBackup(String),
+    InvalidPsbt(String),
+    DerivationMismatch(String),
+    InvalidCvc(String),
+    InvalidCvcLength {
+        len: usize,
+    },
+    WrongCvc {
+        tries_left: usize,
+    },
+    AuthDelayPending {
+        auth_delay: usize,
+    },
+    AlreadyInitialized,
+    MalformedResponse {
+        field: &'static str,
+    },
+    SignRetryExhausted {
+        attempts: usize,
+    },
+    ApduTooLong {
+        len: usize,
+    },
+    ApduStatus {
+        sw1: u8,
+        sw2: u8,
+    },
     UnknownCardType(String),
+    UnsupportedProtocol {
+        got: usize,
+        supported: usize,
+    },
+    Secp256k1(String),
+    Timeout(String),
+    Transport(String),
     #[cfg(feature = "pcsc")]
     PcSc(String),
     #[cfg(feature = "emulator")]
     Emulator(String),
+    #[cfg(feature = "tokio")]
+    Tokio(String),
+    #[cfg(feature = "web-nfc")]
+    WebNfc(String),
+    #[cfg(feature = "qr")]
+    Qr(String),
+    #[cfg(feature = "test-utils")]
+    Mock(String),
+}
+
+impl Error {
+    /// The typed error code if this is an error returned by the card.
+    pub fn cktap_code(&self) -> Option<CkTapErrorCode> {
+        match self {
+            Error::CkTap { code, .. } => CkTapErrorCode::from_code(*code),
+            _ => None,
+        }
+    }
 }
 
+/// Error codes returned by the card, see https://github.com/coinkite/coinkite-tap-proto/blob/master/docs/protocol.md#errors
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CkTapErrorCode {
+    /// rare key generation or signing failure, retry the command
+    UnluckyNumber = 205,
+    /// invalid or missing arguments
+    BadArguments = 400,
+    /// the CVC is incorrect
+    BadAuth = 401,
+    /// command requires authentication
+    NeedsAuth = 403,
+    /// the command is not known
+    UnknownCommand = 404,
+    /// the command is not valid for this card
+    InvalidCommand = 405,
+    /// the card is in the wrong state for this command
+    InvalidState = 406,
+    /// the provided nonce is weak (eg. all same byte)
+    WeakNonce = 417,
+    /// the command is not valid CBOR
+    BadCbor = 422,
+    /// a backup must be taken before this command (TAPSIGNER only)
+    BackupFirst = 425,
+    /// too many bad CVC attempts, use the wait command
+    RateLimited = 429,
+}
+
+impl CkTapErrorCode {
+    pub fn from_code(code: usize) -> Option<Self> {
+        match code {
+            205 => Some(CkTapErrorCode::UnluckyNumber),
+            400 => Some(CkTapErrorCode::BadArguments),
+            401 => Some(CkTapErrorCode::BadAuth),
+            403 => Some(CkTapErrorCode::NeedsAuth),
+            404 => Some(CkTapErrorCode::UnknownCommand),
+            405 => Some(CkTapErrorCode::InvalidCommand),
+            406 => Some(CkTapErrorCode::InvalidState),
+            417 => Some(CkTapErrorCode::WeakNonce),
+            422 => Some(CkTapErrorCode::BadCbor),
+            425 => Some(CkTapErrorCode::BackupFirst),
+            429 => Some(CkTapErrorCode::RateLimited),
+            _ => None,
+        }
+    }
+
+    pub fn code(&self) -> usize {
+        *self as usize
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Error::CiborDe(e) => write!(f, "CBOR deserialization error: {}", e),
+            Error::CiborValue(e) => write!(f, "CBOR value error: {}", e),
+            Error::CkTap { error, code } => write!(f, "Card error {}: {}", code, error),
+            Error::IncorrectSignature(e) => write!(f, "Incorrect signature: {}", e),
+            Error::BadSignature(e) => write!(f, "Bad signature: {}", e),
+            Error::InvalidDigest(e) => write!(f, "Invalid digest: {}", e),
+            Error::InvalidPath(e) => write!(f, "Invalid derivation path: {}", e),
+            Error::InvalidSubpath(e) => write!(f, "Invalid subpath: {}", e),
+            Error::UnsupportedInputType(e) => write!(f, "Unsupported input type: {}", e),
+            Error::InvalidSlot(e) => write!(f, "Invalid slot: {}", e),
+            Error::NonceReuse(e) => write!(f, "Nonce reuse: {}", e),
+            Error::InvalidNonce(e) => write!(f, "Invalid nonce: {}", e),
+            Error::InvalidChainCode(e) => write!(f, "Invalid chain code: {}", e),
+            Error::InvalidPublicKey(e) => write!(f, "Invalid public key: {}", e),
+            Error::InvalidPrivateKey(e) => write!(f, "Invalid private key: {}", e),
+            Error::InvalidAddress(e) => write!(f, "Invalid address: {}", e),
+            Error::InvalidXpub(e) => write!(f, "Invalid xpub: {}", e),
+            Error::InvalidBackup(e) => write!(f, "Invalid backup: {}", e),
+            Error::InvalidPsbt(e) => write!(f, "Invalid PSBT: {}", e),
+            Error::DerivationMismatch(e) => write!(f, "Derivation mismatch: {}", e),
+            Error::InvalidCvc(e) => write!(f, "Invalid CVC: {}", e),
+            Error::InvalidCvcLength { len } => {
+                write!(f, "Invalid CVC length: {} bytes, must be 6 to 32", len)
+            }
+            Error::WrongCvc { tries_left } => {
+                write!(f, "Wrong CVC, {} tries left", tries_left)
+            }
+            Error::AuthDelayPending { auth_delay } => {
+                write!(f, "Auth delay of {} seconds pending", auth_delay)
+            }
+            Error::AlreadyInitialized => write!(f, "Card is already initialized"),
+            Error::MalformedResponse { field } => {
+                write!(f, "Malformed response, bad `{}` field", field)
+            }
+            Error::SignRetryExhausted { attempts } => {
+                write!(
+                    f,
+                    "Card picked an unlucky number in {} sign attempts",
+                    attempts
+                )
+            }
+            Error::ApduStatus { sw1, sw2 } => {
+                write!(f, "APDU status word {:02X}{:02X}", sw1, sw2)
+            }
+            Error::ApduTooLong { len } => {
+                write!(f, "APDU command too long: {} bytes", len)
+            }
+            Error::UnknownCardType(e) => write!(f, "Unknown card type: {}", e),
+            Error::UnsupportedProtocol { got, supported } => write!(
+                f,
+                "Card protocol version {} is newer than the supported version {}",
+                got, supported
+            ),
+            Error::Secp256k1(e) => write!(f, "Secp256k1 error: {}", e),
+            Error::Timeout(e) => write!(f, "Timeout: {}", e),
+            Error::Transport(e) => write!(f, "Transport error: {}", e),
+            #[cfg(feature = "pcsc")]
+            Error::PcSc(e) => write!(f, "PC/SC error: {}", e),
+            #[cfg(feature = "emulator")]
+            Error::Emulator(e) => write!(f, "Emulator error: {}", e),
+            #[cfg(feature = "tokio")]
+            Error::Tokio(e) => write!(f, "Tokio task error: {}", e),
+            #[cfg(feature = "web-nfc")]
+            Error::WebNfc(e) => write!(f, "Web NFC transport error: {}", e),
+            #[cfg(feature = "qr")]
+            Error::Qr(e) => write!(f, "QR code error: {}", e),
+            #[cfg(feature = "test-utils")]
+            Error::Mock(e) => write!(f, "Mock transport error: {}", e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
+
 impl<T> From<ciborium::de::Error<T>> for Error
 where
     T: Debug,
@@ -54,7 +273,29 @@ impl From<ciborium::value::Error> for Error {
 
 impl From<secp256k1::Error> for Error {
     fn from(e: secp256k1::Error) -> Self {
-        Error::IncorrectSignature(e.to_string())
+        Error::Secp256k1(format!("{:?}, {}", e, e))
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<bitcoin::address::Error> for Error {
+    fn from(e: bitcoin::address::Error) -> Self {
+        Error::InvalidAddress(e.to_string())
+    }
+}
+
+/// Derivation failures are path errors, the rest come from decoding an xpub.
+#[cfg(feature = "std")]
+impl From<bitcoin::bip32::Error> for Error {
+    fn from(e: bitcoin::bip32::Error) -> Self {
+        use bitcoin::bip32::Error::*;
+        match e {
+            CannotDeriveFromHardenedKey
+            | InvalidChildNumber(_)
+            | InvalidChildNumberFormat
+            | InvalidDerivationPathFormat => Error::InvalidPath(e.to_string()),
+            _ => Error::InvalidXpub(e.to_string()),
+        }
     }
 }
 
@@ -74,12 +315,12 @@ pub struct ErrorResponse {
 // Apdu Traits
 pub trait CommandApdu {
     fn name() -> String;
-    fn apdu_bytes(&self) -> Vec<u8>
+    fn apdu_bytes(&self) -> Result<Vec<u8>, Error>
     where
         Self: serde::Serialize + Debug,
     {
         let mut command = Vec::new();
-        into_writer(&self, &mut command).unwrap();
+        into_writer(&self, &mut command).map_err(|e| Error::CiborValue(e.to_string()))?;
         build_apdu(&CBOR_CLA_INS_P1P2, command.as_slice())
     }
 }
@@ -98,14 +339,130 @@ pub trait ResponseApdu {
             });
         }
         let cbor_struct: Self = cbor_value.deserialized()?;
+        cbor_struct.validate()?;
         Ok(cbor_struct)
     }
+
+    /// Check the size of fixed length fields, so a truncated or corrupt response fails naming the
+    /// bad field rather than later when a key or signature is parsed.
+    fn validate(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+fn check_field_len(field: &'static str, value: &[u8], len: usize) -> Result<(), Error> {
+    if value.len() != len {
+        return Err(Error::MalformedResponse { field });
+    }
+    Ok(())
+}
+
+fn check_opt_field_len(
+    field: &'static str,
+    value: &Option<Vec<u8>>,
+    len: usize,
+) -> Result<(), Error> {
+    match value {
+        Some(value) => check_field_len(field, value, len),
+        None => Ok(()),
+    }
 }
 
-fn build_apdu(header: &[u8], command: &[u8]) -> Vec<u8> {
+/// Strip the trailing SW1 SW2 status word from a response APDU, for transports like PC/SC or
+/// Android's `IsoDep` that return it with the body. Anything but 0x9000 (success) is returned as
+/// `Error::ApduStatus`, card errors like a bad CVC still come back as CBOR with 0x9000.
+pub fn strip_status_word(mut rapdu: Vec<u8>) -> Result<Vec<u8>, Error> {
+    if rapdu.len() < 2 {
+        return Err(Error::Transport(format!(
+            "Response APDU of {} bytes has no status word",
+            rapdu.len()
+        )));
+    }
+    let sw2 = rapdu.pop().unwrap_or_default();
+    let sw1 = rapdu.pop().unwrap_or_default();
+    if (sw1, sw2) != (0x90, 0x00) {
+        return Err(Error::ApduStatus { sw1, sw2 });
+    }
+    Ok(rapdu)
+}
+
+/// Check an app provided nonce has the required size and is not all the same byte.
+pub fn check_nonce(nonce: &[u8]) -> Result<(), Error> {
+    if nonce.len() != USER_NONCE_SIZE {
+        return Err(Error::InvalidNonce(format!(
+            "Nonce must be {} bytes, got {}",
+            USER_NONCE_SIZE,
+            nonce.len()
+        )));
+    }
+    if nonce.iter().all(|b| *b == nonce[0]) {
+        return Err(Error::InvalidNonce(
+            "Nonce can not be all the same byte".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Check a chain code for `new` is 32 bytes and not all the same byte, a guessable chain code
+/// weakens every key derived from it.
+pub fn check_chain_code(chain_code: &[u8]) -> Result<(), Error> {
+    if chain_code.len() != CHAIN_CODE_SIZE {
+        return Err(Error::InvalidChainCode(format!(
+            "Chain code must be {} bytes, got {}",
+            CHAIN_CODE_SIZE,
+            chain_code.len()
+        )));
+    }
+    if chain_code.iter().all(|b| *b == chain_code[0]) {
+        return Err(Error::InvalidChainCode(
+            "Chain code can not be all the same byte".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Compare bytes in constant time, so checks in the verification paths don't leak how many
+/// leading bytes matched. Different lengths compare unequal.
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    a.ct_eq(b).into()
+}
+
+/// Convert a card path to a BIP-32 derivation path. The card encodes hardened components with the
+/// `HARDENED` (0x80000000) bit set, e.g. `[HARDENED + 84, HARDENED, HARDENED]` is `m/84'/0'/0'`.
+#[cfg(feature = "std")]
+pub(crate) fn derivation_path(path: &[usize]) -> Result<DerivationPath, Error> {
+    path.iter()
+        .map(|index| {
+            u32::try_from(*index).map(ChildNumber::from).map_err(|_| {
+                Error::InvalidPath(format!("Path index {} does not fit in 32 bits", index))
+            })
+        })
+        .collect::<Result<Vec<ChildNumber>, Error>>()
+        .map(DerivationPath::from)
+}
+
+/// Parse a 33 byte SEC1 encoded public key returned in the `field` of a card response.
+fn parse_pubkey(bytes: &[u8], field: &str) -> Result<PublicKey, Error> {
+    PublicKey::from_slice(bytes).map_err(|e| Error::InvalidPublicKey(format!("{}: {}", field, e)))
+}
+
+fn parse_privkey(bytes: &[u8], field: &str) -> Result<SecretKey, Error> {
+    SecretKey::from_slice(bytes).map_err(|e| Error::InvalidPrivateKey(format!("{}: {}", field, e)))
+}
+
+/// Build an APDU from a header (CLA INS P1 P2) and command data. Commands up to 255 bytes use a
+/// single byte Lc, longer commands use the extended length encoding: 0x00 followed by the length
+/// as two big-endian bytes.
+fn build_apdu(header: &[u8], command: &[u8]) -> Result<Vec<u8>, Error> {
     let command_len = command.len();
-    assert!(command_len <= 255, "apdu command too long"); // TODO use Err
-    [header, &[command_len as u8], command].concat()
+    if command_len <= 255 {
+        Ok([header, &[command_len as u8], command].concat())
+    } else if command_len <= u16::MAX as usize {
+        let len_bytes = (command_len as u16).to_be_bytes();
+        Ok([header, &[0x00], &len_bytes, command].concat())
+    } else {
+        Err(Error::ApduTooLong { len: command_len })
+    }
 }
 
 /// Applet Select
@@ -116,7 +473,7 @@ impl CommandApdu for AppletSelect {
     fn name() -> String {
         String::default()
     }
-    fn apdu_bytes(&self) -> Vec<u8> {
+    fn apdu_bytes(&self) -> Result<Vec<u8>, Error> {
         build_apdu(&SELECT_CLA_INS_P1P2, &APP_ID)
     }
 }
@@ -140,7 +497,7 @@ impl CommandApdu for StatusCommand {
     }
 }
 
-#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct StatusResponse {
     pub proto: usize,
     pub ver: String,
@@ -160,13 +517,59 @@ pub struct StatusResponse {
     pub auth_delay: Option<usize>,
 }
 
-impl ResponseApdu for StatusResponse {}
+impl ResponseApdu for StatusResponse {
+    fn validate(&self) -> Result<(), Error> {
+        check_field_len("pubkey", &self.pubkey, PUBKEY_SIZE)?;
+        check_field_len("card_nonce", &self.card_nonce, CARD_NONCE_SIZE)?;
+        // a block height
+        if u32::try_from(self.birth).is_err() {
+            return Err(Error::MalformedResponse { field: "birth" });
+        }
+        Ok(())
+    }
+}
+
+impl StatusResponse {
+    /// The TAPSIGNER's current derivation path, `None` if not yet set up or for a SATSCARD.
+    #[cfg(feature = "std")]
+    pub fn derivation_path(&self) -> Result<Option<DerivationPath>, Error> {
+        self.path.as_deref().map(derivation_path).transpose()
+    }
+
+    /// The network the card is for, `Testnet` if the `testnet` flag is set.
+    #[cfg(feature = "std")]
+    pub fn network(&self) -> Network {
+        if self.testnet == Some(true) {
+            Network::Testnet
+        } else {
+            Network::Bitcoin
+        }
+    }
+
+    /// The card's public key, prefer this over the raw `pubkey` bytes.
+    pub fn pubkey(&self) -> Result<PublicKey, Error> {
+        parse_pubkey(&self.pubkey, "pubkey")
+    }
+
+    /// Classify the card without connecting to it. A SATSCHIP sets both the `tapsigner` and
+    /// `satschip` flags, so they are checked first. Without either flag the card is a SATSCARD
+    /// only if it reports `slots`, else it is unknown.
+    #[cfg(feature = "std")]
+    pub fn card_type(&self) -> Result<crate::CardKind, Error> {
+        match (self.tapsigner == Some(true), self.satschip == Some(true)) {
+            (true, true) => Ok(crate::CardKind::SatsChip),
+            (true, false) => Ok(crate::CardKind::TapSigner),
+            (false, false) if self.slots.is_some() => Ok(crate::CardKind::SatsCard),
+            (_, _) => Err(Error::UnknownCardType("Card not recognized.".to_string())),
+        }
+    }
+}
 
 /// Read Command
 ///
 /// Apps need to write a CBOR message to read a SATSCARD's current payment address, or a
 /// TAPSIGNER's derived public key.
-#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[derive(Serialize, Clone, PartialEq, Eq)]
 pub struct ReadCommand {
     /// 'read' command
     cmd: String,
@@ -181,23 +584,39 @@ pub struct ReadCommand {
     xcvc: Option<Vec<u8>>,
 }
 
+impl Debug for ReadCommand {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("ReadCommand")
+            .field("cmd", &self.cmd)
+            .field("nonce", &self.nonce.to_lower_hex_string())
+            .field(
+                "epubkey",
+                &self.epubkey.as_ref().map(|v| v.to_lower_hex_string()),
+            )
+            .field("xcvc", &self.xcvc.as_ref().map(|_| REDACTED))
+            .finish()
+    }
+}
+
 impl ReadCommand {
-    pub fn authenticated(nonce: Vec<u8>, epubkey: PublicKey, xcvc: Vec<u8>) -> Self {
-        ReadCommand {
+    pub fn authenticated(nonce: Vec<u8>, epubkey: PublicKey, xcvc: Vec<u8>) -> Result<Self, Error> {
+        check_nonce(&nonce)?;
+        Ok(ReadCommand {
             cmd: Self::name(),
             nonce,
             epubkey: Some(epubkey.serialize().to_vec()),
             xcvc: Some(xcvc),
-        }
+        })
     }
 
-    pub fn unauthenticated(nonce: Vec<u8>) -> Self {
-        ReadCommand {
+    pub fn unauthenticated(nonce: Vec<u8>) -> Result<Self, Error> {
+        check_nonce(&nonce)?;
+        Ok(ReadCommand {
             cmd: Self::name(),
             nonce,
             epubkey: None,
             xcvc: None,
-        }
+        })
     }
 }
 
@@ -228,7 +647,14 @@ pub struct ReadResponse {
     pub card_nonce: Vec<u8>,
 }
 
-impl ResponseApdu for ReadResponse {}
+impl ResponseApdu for ReadResponse {
+    fn validate(&self) -> Result<(), Error> {
+        check_field_len("sig", &self.sig, SIG_SIZE)?;
+        check_field_len("pubkey", &self.pubkey, PUBKEY_SIZE)?;
+        check_field_len("card_nonce", &self.card_nonce, CARD_NONCE_SIZE)?;
+        Ok(())
+    }
+}
 
 impl ReadResponse {
     pub fn signature(&self) -> Result<Signature, Error> {
@@ -236,14 +662,41 @@ impl ReadResponse {
         // .expect("Failed to construct ECDSA signature from ReadResponse")
     }
 
-    pub fn pubkey(&self, session_key: Option<SharedSecret>) -> PublicKey {
+    /// Verify the response signature and return the verified public key. TAPSIGNER responses
+    /// need the session key to decrypt the public key.
+    pub fn verify(
+        &self,
+        card_nonce: &[u8],
+        app_nonce: &[u8],
+        slot: u8,
+        session_key: Option<SharedSecret>,
+    ) -> Result<PublicKey, Error> {
+        let mut message_bytes: Vec<u8> = Vec::new();
+        message_bytes.extend("OPENDIME".as_bytes());
+        message_bytes.extend(card_nonce);
+        message_bytes.extend(app_nonce);
+        message_bytes.push(slot);
+        let message = Message::from_hashed_data::<sha256::Hash>(message_bytes.as_slice());
+
+        let pubkey = match session_key {
+            Some(sk) => unzip(&self.pubkey, sk),
+            None => self.pubkey.clone(),
+        };
+        let pubkey = parse_pubkey(&pubkey, "pubkey")?;
+        Secp256k1::verification_only()
+            .verify_ecdsa(&message, &self.signature()?, &pubkey)
+            .map_err(|e| Error::BadSignature(e.to_string()))?;
+        Ok(pubkey)
+    }
+
+    /// The public key, unzipped with the session key if the command was authenticated.
+    pub fn pubkey(&self, session_key: Option<SharedSecret>) -> Result<PublicKey, Error> {
         let pubkey = if let Some(sk) = session_key {
             unzip(&self.pubkey, sk)
         } else {
             self.pubkey.clone()
         };
-        PublicKey::from_slice(pubkey.as_slice())
-            .expect("Failed to construct a PublicKey from ReadResponse")
+        parse_pubkey(&pubkey, "pubkey")
     }
 }
 
@@ -261,28 +714,34 @@ fn unzip(encoded: &[u8], session_key: SharedSecret) -> Vec<u8> {
 
 impl fmt::Display for ReadResponse {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "pubkey: {}", self.pubkey.to_hex())
+        write!(f, "pubkey: {}", self.pubkey.to_lower_hex_string())
     }
 }
 
 impl Debug for ReadResponse {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         f.debug_struct("ReadResponse")
-            .field("sig", &self.sig.to_hex())
-            .field("pubkey", &self.pubkey.to_hex())
-            .field("card_nonce", &self.card_nonce.to_hex())
+            .field("sig", &self.sig.to_lower_hex_string())
+            .field("pubkey", &self.pubkey.to_lower_hex_string())
+            .field("card_nonce", &self.card_nonce.to_lower_hex_string())
             .finish()
     }
 }
 
-// Checks payment address derivation: https://github.com/coinkite/coinkite-tap-proto/blob/master/docs/protocol.md#satscard-checks-payment-address-derivation
-#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+/// Derive Command
+///
+/// SATSCARD: checks payment address derivation, see https://github.com/coinkite/coinkite-tap-proto/blob/master/docs/protocol.md#satscard-checks-payment-address-derivation
+///
+/// TAPSIGNER: performs a hardened BIP-32 derivation and updates the key in use for signing.
+#[derive(Serialize, Clone, PartialEq, Eq)]
 pub struct DeriveCommand {
+    /// 'derive' command
     cmd: String,
     /// provided by app, cannot be all same byte (& should be random), 16 bytes
     #[serde(with = "serde_bytes")]
     nonce: Vec<u8>,
-    path: Vec<u32>, // tapsigner: empty list for `m` case (a no-op)
+    /// (TAPSIGNER only) hardened derivation path, empty list for `m` case (a no-op)
+    path: Vec<u32>,
     /// app's ephemeral public key
     #[serde(with = "serde_bytes")]
     epubkey: Option<Vec<u8>>,
@@ -291,6 +750,21 @@ pub struct DeriveCommand {
     xcvc: Option<Vec<u8>>,
 }
 
+impl Debug for DeriveCommand {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("DeriveCommand")
+            .field("cmd", &self.cmd)
+            .field("nonce", &self.nonce.to_lower_hex_string())
+            .field("path", &self.path)
+            .field(
+                "epubkey",
+                &self.epubkey.as_ref().map(|v| v.to_lower_hex_string()),
+            )
+            .field("xcvc", &self.xcvc.as_ref().map(|_| REDACTED))
+            .finish()
+    }
+}
+
 impl CommandApdu for DeriveCommand {
     fn name() -> String {
         "derive".to_string()
@@ -298,14 +772,15 @@ impl CommandApdu for DeriveCommand {
 }
 
 impl DeriveCommand {
-    pub fn for_satscard(nonce: Vec<u8>) -> Self {
-        DeriveCommand {
+    pub fn for_satscard(nonce: Vec<u8>) -> Result<Self, Error> {
+        check_nonce(&nonce)?;
+        Ok(DeriveCommand {
             cmd: Self::name(),
             nonce,
             path: vec![],
             epubkey: None,
             xcvc: None,
-        }
+        })
     }
 
     pub fn for_tapsigner(
@@ -313,18 +788,28 @@ impl DeriveCommand {
         path: Vec<u32>,
         epubkey: PublicKey,
         xcvc: Vec<u8>,
-    ) -> Self {
-        DeriveCommand {
+    ) -> Result<Self, Error> {
+        check_nonce(&nonce)?;
+        Ok(DeriveCommand {
             cmd: Self::name(),
             nonce,
             path,
             epubkey: Some(epubkey.serialize().to_vec()),
             xcvc: Some(xcvc),
-        }
+        })
     }
 }
 
-#[derive(Deserialize, Clone)]
+/// Derive Response
+///
+/// The signature is created from the digest (SHA-256) of these bytes, using the derived key (or the
+/// master key if no path was given):
+///
+/// b'OPENDIME' (8 bytes)
+/// (card_nonce - 16 bytes)
+/// (nonce from derive command - 16 bytes)
+/// (chain_code - 32 bytes)
+#[derive(Serialize, Deserialize, Clone)]
 pub struct DeriveResponse {
     #[serde(with = "serde_bytes")]
     pub sig: Vec<u8>, // 64 bytes
@@ -343,16 +828,43 @@ pub struct DeriveResponse {
     pub card_nonce: Vec<u8>, // 16 bytes
 }
 
-impl ResponseApdu for DeriveResponse {}
+impl ResponseApdu for DeriveResponse {
+    fn validate(&self) -> Result<(), Error> {
+        check_field_len("sig", &self.sig, SIG_SIZE)?;
+        check_field_len("chain_code", &self.chain_code, CHAIN_CODE_SIZE)?;
+        check_field_len("master_pubkey", &self.master_pubkey, PUBKEY_SIZE)?;
+        check_opt_field_len("pubkey", &self.pubkey, PUBKEY_SIZE)?;
+        check_field_len("card_nonce", &self.card_nonce, CARD_NONCE_SIZE)?;
+        Ok(())
+    }
+}
+
+impl DeriveResponse {
+    /// The master public key (`m`), prefer this over the raw `master_pubkey` bytes.
+    pub fn master_pubkey(&self) -> Result<PublicKey, Error> {
+        parse_pubkey(&self.master_pubkey, "master_pubkey")
+    }
+
+    /// The derived public key, `None` if no path was given.
+    pub fn pubkey(&self) -> Result<Option<PublicKey>, Error> {
+        self.pubkey
+            .as_ref()
+            .map(|pk| parse_pubkey(pk, "pubkey"))
+            .transpose()
+    }
+}
 
 impl Debug for DeriveResponse {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         f.debug_struct("DeriveResponse")
-            .field("sig", &self.sig.to_hex())
-            .field("chain_code", &self.chain_code.to_hex())
-            .field("master_pubkey", &self.master_pubkey.to_hex())
-            .field("pubkey", &self.pubkey.clone().map(|pk| pk.to_hex()))
-            .field("card_nonce", &self.card_nonce.to_hex())
+            .field("sig", &self.sig.to_lower_hex_string())
+            .field("chain_code", &self.chain_code.to_lower_hex_string())
+            .field("master_pubkey", &self.master_pubkey.to_lower_hex_string())
+            .field(
+                "pubkey",
+                &self.pubkey.clone().map(|pk| pk.to_lower_hex_string()),
+            )
+            .field("card_nonce", &self.card_nonce.to_lower_hex_string())
             .finish()
     }
 }
@@ -383,31 +895,94 @@ impl Default for CertsCommand {
 /// Each entry in the list is a 65-byte signature. The first signature signs the card's public key,
 /// and each following signature signs the public key used in the previous signature. Although two
 /// levels of signatures are planned, more are possible.
-#[derive(Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct CertsResponse {
-    /// list of certificates, from 'batch' to 'root'
-    // TODO create custom deserializer like "serde_bytes" but for Vec<Vec<u8>>
-    cert_chain: Vec<Value>,
+    /// list of certificates, from 'batch' to 'root', 65 bytes each
+    cert_chain: Vec<ByteBuf>,
 }
 
 impl ResponseApdu for CertsResponse {}
 
 impl CertsResponse {
     pub fn cert_chain(&self) -> Vec<Vec<u8>> {
-        self.clone()
-            .cert_chain
-            .into_iter()
-            .filter_map(|v| match v {
-                Value::Bytes(bv) => Some(bv),
-                _ => None,
-            })
+        self.cert_chain
+            .iter()
+            .map(|cert| cert.clone().into_vec())
             .collect()
     }
+
+    /// Recover the key behind each certificate, starting from the card's public key.
+    pub fn chain(&self, card_pubkey: &PublicKey) -> Result<CertChain, Error> {
+        let secp = Secp256k1::verification_only();
+        let mut keys = vec![*card_pubkey];
+        for sig in &self.cert_chain {
+            if sig.len() != 65 {
+                return Err(Error::IncorrectSignature(format!(
+                    "Certificate signature must be 65 bytes, got {}",
+                    sig.len()
+                )));
+            }
+            // BIP-137: https://github.com/bitcoin/bips/blob/master/bip-0137.mediawiki
+            let subtract_by = match sig[0] {
+                27..=30 => 27, // P2PKH uncompressed
+                31..=34 => 31, // P2PKH compressed
+                35..=38 => 35, // Segwit P2SH
+                39..=42 => 39, // Segwit Bech32
+                _ => {
+                    return Err(Error::IncorrectSignature(
+                        "Unrecognized BIP-137 address".to_string(),
+                    ))
+                }
+            };
+            let rec_id = RecoveryId::from_i32((sig[0] as i32) - subtract_by)?;
+            let rec_sig = RecoverableSignature::from_compact(&sig[1..], rec_id)?;
+            let signed = keys[keys.len() - 1].serialize();
+            let md = Message::from_hashed_data::<sha256::Hash>(&signed);
+            keys.push(secp.recover_ecdsa(&md, &rec_sig)?);
+        }
+        Ok(CertChain { keys })
+    }
+}
+
+/// Keys of a card's certificate chain, each one signed by the next: the card key, the factory
+/// batch key that signed it and so on up to the root key. Only the root is checked against the
+/// published factory keys, see `FactoryRootKey`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CertChain {
+    keys: Vec<PublicKey>,
+}
+
+impl CertChain {
+    /// All keys, from the card key up to the root.
+    pub fn keys(&self) -> &[PublicKey] {
+        &self.keys
+    }
+
+    pub fn card_key(&self) -> &PublicKey {
+        &self.keys[0]
+    }
+
+    /// The factory batch key that signed the card key, `None` if the root signed it directly.
+    pub fn batch_key(&self) -> Option<&PublicKey> {
+        match self.keys.len() {
+            0..=2 => None,
+            _ => Some(&self.keys[1]),
+        }
+    }
+
+    /// The last key of the chain, the card key itself for an empty chain.
+    pub fn root_key(&self) -> &PublicKey {
+        &self.keys[self.keys.len() - 1]
+    }
 }
 
 impl Debug for CertsResponse {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        let cert_hexes: Vec<String> = self.cert_chain().iter().map(|key| key.to_hex()).collect();
+        let cert_hexes: Vec<String> = self
+            .cert_chain()
+            .iter()
+            .map(|key| key.to_lower_hex_string())
+            .collect();
         f.debug_struct("CertsResponse")
             .field("cert_chain", &cert_hexes)
             .finish()
@@ -434,17 +1009,18 @@ impl CommandApdu for CheckCommand {
 }
 
 impl CheckCommand {
-    pub fn new(nonce: Vec<u8>) -> Self {
-        CheckCommand {
+    pub fn new(nonce: Vec<u8>) -> Result<Self, Error> {
+        check_nonce(&nonce)?;
+        Ok(CheckCommand {
             cmd: Self::name(),
             nonce,
-        }
+        })
     }
 }
 
 /// Check Certs Response
 /// ref: https://github.com/coinkite/coinkite-tap-proto/blob/master/docs/protocol.md#certs
-#[derive(Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct CheckResponse {
     /// signature using card_pubkey, 64 bytes
     #[serde(with = "serde_bytes")]
@@ -454,13 +1030,19 @@ pub struct CheckResponse {
     pub card_nonce: Vec<u8>,
 }
 
-impl ResponseApdu for CheckResponse {}
+impl ResponseApdu for CheckResponse {
+    fn validate(&self) -> Result<(), Error> {
+        check_field_len("auth_sig", &self.auth_sig, SIG_SIZE)?;
+        check_field_len("card_nonce", &self.card_nonce, CARD_NONCE_SIZE)?;
+        Ok(())
+    }
+}
 
 impl Debug for CheckResponse {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         f.debug_struct("CheckResponse")
-            .field("auth_sig", &self.auth_sig.to_hex())
-            .field("card_nonce", &self.card_nonce.to_hex())
+            .field("auth_sig", &self.auth_sig.to_lower_hex_string())
+            .field("card_nonce", &self.card_nonce.to_lower_hex_string())
             .finish()
     }
 }
@@ -486,60 +1068,163 @@ impl CommandApdu for NfcCommand {
 /// nfc Response
 ///
 /// URL for smart phone to navigate to
-#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct NfcResponse {
-    /// command result
+    /// command result, without the `https://` scheme
     pub url: String,
+    /// new nonce value, for NEXT command (not this one), 16 bytes
+    #[serde(with = "serde_bytes")]
+    #[serde(default)]
+    pub card_nonce: Option<Vec<u8>>,
 }
 
-impl ResponseApdu for NfcResponse {}
+impl ResponseApdu for NfcResponse {
+    fn validate(&self) -> Result<(), Error> {
+        check_opt_field_len("card_nonce", &self.card_nonce, CARD_NONCE_SIZE)?;
+        Ok(())
+    }
+}
+
+impl NfcResponse {
+    /// Parse the url into its base and the query params carried in the fragment.
+    pub fn url(&self) -> Result<NfcUrl, Error> {
+        NfcUrl::parse(&self.url)
+    }
+}
+
+/// NFC url, eg. `getsatscard.com/start#u=S&o=0&r=...&n=...&s=...`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NfcUrl {
+    /// host the phone navigates to, eg. `getsatscard.com` or `tapsigner.com`
+    pub host: String,
+    /// path on the host, eg. `/start`
+    pub path: String,
+    /// key value params from the url fragment, in order
+    pub params: Vec<(String, String)>,
+}
+
+impl NfcUrl {
+    pub fn parse(url: &str) -> Result<Self, Error> {
+        let url = url
+            .strip_prefix("https://")
+            .or_else(|| url.strip_prefix("http://"))
+            .unwrap_or(url);
+        let (base, fragment) = url.split_once('#').unwrap_or((url, ""));
+        let (host, path) = match base.find('/') {
+            Some(index) => base.split_at(index),
+            None => (base, ""),
+        };
+        if host.is_empty() {
+            return Err(Error::CiborValue(format!(
+                "Missing host in NFC url {}",
+                url
+            )));
+        }
+        let params = fragment
+            .split('&')
+            .filter(|param| !param.is_empty())
+            .map(|param| {
+                let (key, value) = param.split_once('=').unwrap_or((param, ""));
+                (key.to_string(), value.to_string())
+            })
+            .collect();
+        Ok(NfcUrl {
+            host: host.to_string(),
+            path: path.to_string(),
+            params,
+        })
+    }
+
+    /// The value of the first param with the given key.
+    pub fn param(&self, key: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Digest signed by a SATSCARD slot key, the SHA-256 of the fragment up to and including `s=`.
+    pub fn signed_digest(&self) -> Result<Message, Error> {
+        let mut fragment = String::new();
+        for (key, value) in &self.params {
+            if key == "s" {
+                fragment.push_str("s=");
+                return Ok(Message::from_hashed_data::<sha256::Hash>(
+                    fragment.as_bytes(),
+                ));
+            }
+            fragment.push_str(&format!("{}={}&", key, value));
+        }
+        Err(Error::CiborValue("Missing s param in NFC url".to_string()))
+    }
+
+    /// The 64 byte compact signature in the `s` param.
+    pub fn signature(&self) -> Result<Vec<u8>, Error> {
+        let sig = self
+            .param("s")
+            .ok_or_else(|| Error::CiborValue("Missing s param in NFC url".to_string()))?;
+        Vec::<u8>::from_hex(sig).map_err(|e| Error::CiborValue(e.to_string()))
+    }
+}
+
+impl fmt::Display for NfcUrl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "https://{}{}", self.host, self.path)?;
+        for (i, (key, value)) in self.params.iter().enumerate() {
+            let sep = if i == 0 { '#' } else { '&' };
+            write!(f, "{}{}={}", sep, key, value)?;
+        }
+        Ok(())
+    }
+}
 
 /// Sign Command
-// {
-//     'cmd': 'sign',              # command
-//     'slot': 0,                  # (optional) which slot's to key to use, must be unsealed.
-//     'subpath': [0, 0],          # (TAPSIGNER only) additional derivation keypath to be used
-//     'digest': (32 bytes),        # message digest to be signed
-//     'epubkey': (33 bytes),       # app's ephemeral public key
-//     'xcvc': (6 bytes)          # encrypted CVC value
-// }
-#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+///
+/// TAPSIGNER: sign an arbitrary 32 byte message digest using the key at the card's current
+/// derivation path, optionally extended by up to two additional non-hardened subpath components.
+///
+/// SATSCARD: sign a digest with the private key of an unsealed slot.
+#[derive(Serialize, Clone, PartialEq, Eq)]
 pub struct SignCommand {
+    /// 'sign' command
     cmd: String,
+    /// (optional) which slot's key to use, must be unsealed
     slot: Option<u8>,
-    subpath: Option<[u32; 2]>,
-    // additional keypath for TapSigner only
+    /// (TAPSIGNER only) additional derivation keypath to be used
+    subpath: Option<Vec<u32>>,
+    /// message digest to be signed, 32 bytes
     #[serde(with = "serde_bytes")]
     digest: Vec<u8>,
-    // message digest to be signed
+    /// app's ephemeral public key, 33 bytes
     #[serde(with = "serde_bytes")]
     epubkey: Vec<u8>,
+    /// encrypted CVC value, 6 to 32 bytes
     #[serde(with = "serde_bytes")]
     xcvc: Vec<u8>,
 }
 
-impl SignCommand {
-    // pub fn for_satscard(slot: Option<u8>, digest: Vec<u8>, epubkey: Vec<u8>, xcvc: Vec<u8>) -> Self {
-    //     Self {
-    //         cmd: "sign".to_string(),
-    //         slot,
-    //         digest,
-    //         subpath: None,
-    //         epubkey,
-    //         xcvc,
-    //     }
-    // }
+impl Debug for SignCommand {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("SignCommand")
+            .field("cmd", &self.cmd)
+            .field("slot", &self.slot)
+            .field("subpath", &self.subpath)
+            .field("digest", &self.digest.to_lower_hex_string())
+            .field("epubkey", &self.epubkey.to_lower_hex_string())
+            .field("xcvc", &REDACTED)
+            .finish()
+    }
+}
 
+impl SignCommand {
     pub fn for_tapsigner(
-        subpath: Option<[u32; 2]>,
+        subpath: Option<Vec<u32>>,
         digest: Vec<u8>,
         epubkey: PublicKey,
         xcvc: Vec<u8>,
     ) -> Self {
-        let cmd = Self::name();
-
         SignCommand {
-            cmd,
+            cmd: Self::name(),
             slot: Some(0),
             subpath,
             digest,
@@ -556,30 +1241,55 @@ impl CommandApdu for SignCommand {
 }
 
 /// Sign Response
-// SATSCARD: Arbitrary signatures can be created for unsealed slots. The app could perform this, since the private key is known, but it's best if the app isn't contaminated with private key information. This could be used for both spending and multisig wallet operations.
-//
-// TAPSIGNER: This is its core feature — signing an arbitrary message digest with a tap. Once the card is set up (the key is picked), the command will always be valid.
+///
+/// SATSCARD: Arbitrary signatures can be created for unsealed slots. The app could perform this,
+/// since the private key is known, but it's best if the app isn't contaminated with private key
+/// information. This could be used for both spending and multisig wallet operations.
+///
+/// TAPSIGNER: This is its core feature — signing an arbitrary message digest with a tap. Once the
+/// card is set up (the key is picked), the command will always be valid.
 #[derive(Deserialize, Clone, PartialEq, Eq)]
 pub struct SignResponse {
-    /// command result
+    /// slot used for signing
     pub slot: u8,
+    /// signature over the digest, 64 bytes
     #[serde(with = "serde_bytes")]
     pub sig: Vec<u8>,
+    /// public key of the key used to sign, 33 bytes
     #[serde(with = "serde_bytes")]
     pub pubkey: Vec<u8>,
+    /// new nonce value, for NEXT command (not this one), 16 bytes
     #[serde(with = "serde_bytes")]
     pub card_nonce: Vec<u8>,
 }
 
-impl ResponseApdu for SignResponse {}
+impl ResponseApdu for SignResponse {
+    fn validate(&self) -> Result<(), Error> {
+        check_field_len("sig", &self.sig, SIG_SIZE)?;
+        check_field_len("pubkey", &self.pubkey, PUBKEY_SIZE)?;
+        check_field_len("card_nonce", &self.card_nonce, CARD_NONCE_SIZE)?;
+        Ok(())
+    }
+}
+
+impl SignResponse {
+    /// Public key of the key used to sign, prefer this over the raw `pubkey` bytes.
+    pub fn pubkey(&self) -> Result<PublicKey, Error> {
+        parse_pubkey(&self.pubkey, "pubkey")
+    }
+
+    pub fn signature(&self) -> Result<Signature, Error> {
+        Signature::from_compact(self.sig.as_slice()).map_err(|e| Error::CiborValue(e.to_string()))
+    }
+}
 
 impl Debug for SignResponse {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         f.debug_struct("SignResponse")
             .field("slot", &self.slot)
-            .field("sig", &self.sig.to_hex())
-            .field("pubkey", &self.pubkey.to_hex())
-            .field("card_nonce", &self.card_nonce.to_hex())
+            .field("sig", &self.sig.to_lower_hex_string())
+            .field("pubkey", &self.pubkey.to_lower_hex_string())
+            .field("card_nonce", &self.card_nonce.to_lower_hex_string())
             .finish()
     }
 }
@@ -594,7 +1304,7 @@ impl Debug for SignResponse {
 ///
 /// The wait command takes one second to execute and reduces the auth_delay by one unit. Typically,
 /// 15 wait commands need to be executed before retrying a CVC.
-#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[derive(Serialize, Clone, PartialEq, Eq)]
 pub struct WaitCommand {
     /// 'wait' command
     cmd: String,
@@ -606,6 +1316,19 @@ pub struct WaitCommand {
     xcvc: Option<Vec<u8>>,
 }
 
+impl Debug for WaitCommand {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("WaitCommand")
+            .field("cmd", &self.cmd)
+            .field(
+                "epubkey",
+                &self.epubkey.as_ref().map(|v| v.to_lower_hex_string()),
+            )
+            .field("xcvc", &self.xcvc.as_ref().map(|_| REDACTED))
+            .finish()
+    }
+}
+
 impl WaitCommand {
     pub fn new(epubkey: Option<Vec<u8>>, xcvc: Option<Vec<u8>>) -> Self {
         WaitCommand {
@@ -625,16 +1348,25 @@ impl CommandApdu for WaitCommand {
 /// Wait Response
 ///
 /// When auth_delay is zero, the CVC can be retried and tested without side effects.
-#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct WaitResponse {
     /// command result
     pub success: bool,
     /// how much more delay is now required
     #[serde(default)]
     pub auth_delay: usize,
+    /// new nonce value, for NEXT command (not this one), 16 bytes
+    #[serde(with = "serde_bytes")]
+    #[serde(default)]
+    pub card_nonce: Option<Vec<u8>>,
 }
 
-impl ResponseApdu for WaitResponse {}
+impl ResponseApdu for WaitResponse {
+    fn validate(&self) -> Result<(), Error> {
+        check_opt_field_len("card_nonce", &self.card_nonce, CARD_NONCE_SIZE)?;
+        Ok(())
+    }
+}
 
 /// New Command
 ///
@@ -643,11 +1375,12 @@ impl ResponseApdu for WaitResponse {}
 /// TAPSIGNER: This command is only used once.
 ///
 /// The slot number is included in the request to prevent command replay.
-#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[derive(Serialize, Clone, PartialEq, Eq)]
 pub struct NewCommand {
     /// 'new' command
     cmd: String,
-    /// (use 0 for TapSigner) slot to be affected, must equal currently-active slot number
+    /// (use 0 for TapSigner) slot to be affected: the active slot if it is unused, or the following
+    /// slot if the active slot is unsealed
     slot: u8,
     /// app's entropy share to be applied to new slot (optional on SATSCARD)
     #[serde(with = "serde_bytes")]
@@ -660,6 +1393,21 @@ pub struct NewCommand {
     xcvc: Vec<u8>, // 6 bytes
 }
 
+impl Debug for NewCommand {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("NewCommand")
+            .field("cmd", &self.cmd)
+            .field("slot", &self.slot)
+            .field(
+                "chain_code",
+                &self.chain_code.as_ref().map(|v| v.to_lower_hex_string()),
+            )
+            .field("epubkey", &self.epubkey.to_lower_hex_string())
+            .field("xcvc", &REDACTED)
+            .finish()
+    }
+}
+
 impl NewCommand {
     pub fn new(
         slot: Option<u8>,
@@ -696,7 +1444,7 @@ impl CommandApdu for NewCommand {
 ///
 /// In either case, the status and read commands are required to learn the details of the new
 /// address/key.
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct NewResponse {
     /// slot just made
     pub slot: u8,
@@ -705,7 +1453,12 @@ pub struct NewResponse {
     pub card_nonce: Vec<u8>, // 16 bytes
 }
 
-impl ResponseApdu for NewResponse {}
+impl ResponseApdu for NewResponse {
+    fn validate(&self) -> Result<(), Error> {
+        check_field_len("card_nonce", &self.card_nonce, CARD_NONCE_SIZE)?;
+        Ok(())
+    }
+}
 
 impl fmt::Display for NewResponse {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -717,7 +1470,7 @@ impl fmt::Display for NewResponse {
 ///
 /// Unseal the current slot.
 /// NOTE: The slot number is included in the request to prevent command replay. Only the current slot can be unsealed.
-#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[derive(Serialize, Clone, PartialEq, Eq)]
 pub struct UnsealCommand {
     /// 'unseal' command
     cmd: String,
@@ -731,6 +1484,17 @@ pub struct UnsealCommand {
     xcvc: Vec<u8>,
 }
 
+impl Debug for UnsealCommand {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("UnsealCommand")
+            .field("cmd", &self.cmd)
+            .field("slot", &self.slot)
+            .field("epubkey", &self.epubkey.to_lower_hex_string())
+            .field("xcvc", &REDACTED)
+            .finish()
+    }
+}
+
 impl UnsealCommand {
     pub fn new(slot: u8, epubkey: Vec<u8>, xcvc: Vec<u8>) -> Self {
         UnsealCommand {
@@ -749,12 +1513,12 @@ impl CommandApdu for UnsealCommand {
 }
 
 /// Unseal Response
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Deserialize, Clone)]
 pub struct UnsealResponse {
     /// slot just unsealed
     pub slot: u8,
-    /// private key for spending (for addr), 32 bytes
-    /// The private keys are encrypted, XORed with the session key
+    /// private key for spending (for addr), 32 bytes. The card sends it XORed with the session key,
+    /// `SatsCard::unseal` returns it decrypted
     #[serde(with = "serde_bytes")]
     pub privkey: Vec<u8>,
     /// slot's pubkey (convenience, since could be calc'd from privkey), 33 bytes
@@ -771,17 +1535,71 @@ pub struct UnsealResponse {
     pub card_nonce: Vec<u8>,
 }
 
-impl ResponseApdu for UnsealResponse {}
+impl Debug for UnsealResponse {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("UnsealResponse")
+            .field("slot", &self.slot)
+            .field("privkey", &REDACTED)
+            .field("pubkey", &self.pubkey.to_lower_hex_string())
+            .field("master_pk", &REDACTED)
+            .field("chain_code", &self.chain_code.to_lower_hex_string())
+            .field("card_nonce", &self.card_nonce.to_lower_hex_string())
+            .finish()
+    }
+}
 
+impl ResponseApdu for UnsealResponse {
+    fn validate(&self) -> Result<(), Error> {
+        check_field_len("privkey", &self.privkey, KEY_SIZE)?;
+        check_field_len("pubkey", &self.pubkey, PUBKEY_SIZE)?;
+        check_field_len("master_pk", &self.master_pk, KEY_SIZE)?;
+        check_field_len("chain_code", &self.chain_code, CHAIN_CODE_SIZE)?;
+        check_field_len("card_nonce", &self.card_nonce, CARD_NONCE_SIZE)?;
+        Ok(())
+    }
+}
+
+impl Drop for UnsealResponse {
+    fn drop(&mut self) {
+        self.privkey.zeroize();
+        self.master_pk.zeroize();
+    }
+}
+
+impl UnsealResponse {
+    /// The slot's public key, prefer this over the raw `pubkey` bytes.
+    pub fn pubkey(&self) -> Result<PublicKey, Error> {
+        parse_pubkey(&self.pubkey, "pubkey")
+    }
+
+    /// The slot's private key, prefer this over the raw `privkey` bytes. Only valid once
+    /// `SatsCard::unseal` has decrypted it with the session key.
+    pub fn privkey(&self) -> Result<SecretKey, Error> {
+        parse_privkey(&self.privkey, "privkey")
+    }
+
+    /// The card's master private key.
+    pub fn master_pk(&self) -> Result<SecretKey, Error> {
+        parse_privkey(&self.master_pk, "master_pk")
+    }
+}
+
+/// The private keys are redacted like in `Debug`, read them with `privkey` and `master_pk`.
 impl fmt::Display for UnsealResponse {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let master = XOnlyPublicKey::from_slice(self.master_pk.as_slice()).unwrap();
-        let pubkey = PublicKey::from_slice(self.pubkey.as_slice()).unwrap();
-        let privkey = SecretKey::from_slice(self.privkey.as_slice()).unwrap();
         writeln!(f, "slot: {}", self.slot)?;
-        writeln!(f, "master_pk: {}", master)?;
-        writeln!(f, "pubkey: {}", pubkey)?;
-        writeln!(f, "privkey: {}", privkey.display_secret())
+        match self.master_pk() {
+            Ok(_) => writeln!(f, "master_pk: {}", REDACTED)?,
+            Err(e) => writeln!(f, "master_pk: {}", e)?,
+        }
+        match self.pubkey() {
+            Ok(pubkey) => writeln!(f, "pubkey: {}", pubkey)?,
+            Err(e) => writeln!(f, "pubkey: {}", e)?,
+        }
+        match self.privkey() {
+            Ok(_) => writeln!(f, "privkey: {}", REDACTED),
+            Err(e) => writeln!(f, "privkey: {}", e),
+        }
     }
 }
 
@@ -796,7 +1614,7 @@ impl fmt::Display for UnsealResponse {
 ///
 /// Incorrect auth values for xcvc will fail as normal. Omit the xcvc and epubkey value to proceed
 /// without authentication if CVC is unknown.
-#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[derive(Serialize, Clone, PartialEq, Eq)]
 pub struct DumpCommand {
     /// 'dump' command
     cmd: String,
@@ -812,6 +1630,20 @@ pub struct DumpCommand {
     xcvc: Option<Vec<u8>>,
 }
 
+impl Debug for DumpCommand {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("DumpCommand")
+            .field("cmd", &self.cmd)
+            .field("slot", &self.slot)
+            .field(
+                "epubkey",
+                &self.epubkey.as_ref().map(|v| v.to_lower_hex_string()),
+            )
+            .field("xcvc", &self.xcvc.as_ref().map(|_| REDACTED))
+            .finish()
+    }
+}
+
 impl DumpCommand {
     pub fn new(slot: usize, epubkey: Option<Vec<u8>>, xcvc: Option<Vec<u8>>) -> Self {
         DumpCommand {
@@ -833,7 +1665,7 @@ impl CommandApdu for DumpCommand {
 ///
 /// Without the CVC, the dump command returns just the sealed/unsealed/unused status for each slot,
 /// with the exception of unsealed slots where the address in full is also provided.
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Deserialize, Clone)]
 pub struct DumpResponse {
     /// slot just made
     pub slot: usize,
@@ -870,10 +1702,59 @@ pub struct DumpResponse {
     pub card_nonce: Vec<u8>,
 }
 
-impl ResponseApdu for DumpResponse {}
+impl Debug for DumpResponse {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("DumpResponse")
+            .field("slot", &self.slot)
+            .field("privkey", &self.privkey.as_ref().map(|_| REDACTED))
+            .field("pubkey", &self.pubkey.to_lower_hex_string())
+            .field(
+                "chain_code",
+                &self.chain_code.as_ref().map(|v| v.to_lower_hex_string()),
+            )
+            .field("master_pk", &self.master_pk.as_ref().map(|_| REDACTED))
+            .field("tampered", &self.tampered)
+            .field("used", &self.used)
+            .field("sealed", &self.sealed)
+            .field("addr", &self.addr)
+            .field("card_nonce", &self.card_nonce.to_lower_hex_string())
+            .finish()
+    }
+}
+
+impl ResponseApdu for DumpResponse {
+    fn validate(&self) -> Result<(), Error> {
+        check_opt_field_len("privkey", &self.privkey, KEY_SIZE)?;
+        if !self.pubkey.is_empty() {
+            check_field_len("pubkey", &self.pubkey, PUBKEY_SIZE)?;
+        }
+        check_opt_field_len("chain_code", &self.chain_code, CHAIN_CODE_SIZE)?;
+        check_opt_field_len("master_pk", &self.master_pk, KEY_SIZE)?;
+        check_field_len("card_nonce", &self.card_nonce, CARD_NONCE_SIZE)?;
+        Ok(())
+    }
+}
+
+impl DumpResponse {
+    /// The slot's public key, `None` if the card didn't include it.
+    pub fn pubkey(&self) -> Result<Option<PublicKey>, Error> {
+        if self.pubkey.is_empty() {
+            Ok(None)
+        } else {
+            parse_pubkey(&self.pubkey, "pubkey").map(Some)
+        }
+    }
+}
+
+impl Drop for DumpResponse {
+    fn drop(&mut self) {
+        self.privkey.zeroize();
+        self.master_pk.zeroize();
+    }
+}
 
 /// TAPSIGNER only - Provides the current XPUB (BIP-32 serialized), either at the top level (master) or the derived key in use (see 'path' value in status response)
-#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[derive(Serialize, Clone, PartialEq, Eq)]
 pub struct XpubCommand {
     cmd: String,  // always "xpub"
     master: bool, // give master (`m`) XPUB, otherwise derived XPUB
@@ -883,6 +1764,17 @@ pub struct XpubCommand {
     xcvc: Vec<u8>, //encrypted CVC value (required)
 }
 
+impl Debug for XpubCommand {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("XpubCommand")
+            .field("cmd", &self.cmd)
+            .field("master", &self.master)
+            .field("epubkey", &self.epubkey.to_lower_hex_string())
+            .field("xcvc", &REDACTED)
+            .finish()
+    }
+}
+
 impl CommandApdu for XpubCommand {
     fn name() -> String {
         "xpub".to_string()
@@ -900,21 +1792,535 @@ impl XpubCommand {
     }
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct XpubResponse {
+    /// BIP-32 serialized xpub, 78 bytes
     #[serde(with = "serde_bytes")]
     pub xpub: Vec<u8>,
+    /// new nonce value, for NEXT command (not this one), 16 bytes
     #[serde(with = "serde_bytes")]
     pub card_nonce: Vec<u8>,
 }
 
-impl ResponseApdu for XpubResponse {}
+impl ResponseApdu for XpubResponse {
+    fn validate(&self) -> Result<(), Error> {
+        check_field_len("xpub", &self.xpub, XPUB_SIZE)?;
+        check_field_len("card_nonce", &self.card_nonce, CARD_NONCE_SIZE)?;
+        Ok(())
+    }
+}
+
+impl XpubResponse {
+    /// The decoded xpub, prefer this over the raw `xpub` bytes.
+    #[cfg(feature = "std")]
+    pub fn xpub(&self) -> Result<Xpub, Error> {
+        Xpub::decode(self.xpub.as_slice())
+            .map_err(|e| Error::InvalidXpub(format!("Bad xpub field: {}", e)))
+    }
+}
 
 impl Debug for XpubResponse {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         f.debug_struct("XpubResponse")
-            .field("xpub", &self.xpub.to_hex())
-            .field("card_nonce", &self.card_nonce.to_hex())
+            .field("xpub", &self.xpub.to_lower_hex_string())
+            .field("card_nonce", &self.card_nonce.to_lower_hex_string())
+            .finish()
+    }
+}
+
+/// TAPSIGNER only - Change the CVC used for card authentication to a new value. The new CVC must
+/// be 6 to 32 bytes long and is sent XORed with the session key.
+#[derive(Serialize, Clone, PartialEq, Eq)]
+pub struct ChangeCommand {
+    /// 'change' command
+    cmd: String,
+    /// new CVC, encrypted
+    #[serde(with = "serde_bytes")]
+    data: Vec<u8>, // 6 to 32 bytes
+    /// app's ephemeral public key
+    #[serde(with = "serde_bytes")]
+    epubkey: Vec<u8>, // 33 bytes
+    /// encrypted CVC value
+    #[serde(with = "serde_bytes")]
+    xcvc: Vec<u8>, // 6 bytes
+}
+
+impl Debug for ChangeCommand {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("ChangeCommand")
+            .field("cmd", &self.cmd)
+            .field("data", &REDACTED)
+            .field("epubkey", &self.epubkey.to_lower_hex_string())
+            .field("xcvc", &REDACTED)
+            .finish()
+    }
+}
+
+impl CommandApdu for ChangeCommand {
+    fn name() -> String {
+        "change".to_string()
+    }
+}
+
+impl ChangeCommand {
+    pub fn new(data: Vec<u8>, epubkey: PublicKey, xcvc: Vec<u8>) -> Self {
+        Self {
+            cmd: Self::name(),
+            data,
+            epubkey: epubkey.serialize().to_vec(),
+            xcvc,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct ChangeResponse {
+    /// true if the CVC was changed
+    pub success: bool,
+    /// new nonce value, for NEXT command (not this one), 16 bytes
+    #[serde(with = "serde_bytes")]
+    pub card_nonce: Vec<u8>,
+}
+
+impl ResponseApdu for ChangeResponse {
+    fn validate(&self) -> Result<(), Error> {
+        check_field_len("card_nonce", &self.card_nonce, CARD_NONCE_SIZE)?;
+        Ok(())
+    }
+}
+
+impl Debug for ChangeResponse {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("ChangeResponse")
+            .field("success", &self.success)
+            .field("card_nonce", &self.card_nonce.to_lower_hex_string())
             .finish()
     }
 }
+
+/// TAPSIGNER only - Get an encrypted backup of the card's master private key.
+///
+/// The backup is encrypted with AES-128-CTR, using the 128 bit backup key printed on the card and a
+/// zero IV. Decrypted it is ASCII text: the XPRV on the first line and the derivation path in use
+/// (eg. `m/84h/0h/0h`) on the second. The card counts backups in the status `num_backups` field
+/// and will only make a limited number of them before refusing further requests.
+#[derive(Serialize, Clone, PartialEq, Eq)]
+pub struct BackupCommand {
+    /// 'backup' command
+    cmd: String,
+    /// app's ephemeral public key
+    #[serde(with = "serde_bytes")]
+    epubkey: Vec<u8>, // 33 bytes
+    /// encrypted CVC value
+    #[serde(with = "serde_bytes")]
+    xcvc: Vec<u8>, // 6 bytes
+}
+
+impl Debug for BackupCommand {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("BackupCommand")
+            .field("cmd", &self.cmd)
+            .field("epubkey", &self.epubkey.to_lower_hex_string())
+            .field("xcvc", &REDACTED)
+            .finish()
+    }
+}
+
+impl CommandApdu for BackupCommand {
+    fn name() -> String {
+        "backup".to_string()
+    }
+}
+
+impl BackupCommand {
+    pub fn new(epubkey: PublicKey, xcvc: Vec<u8>) -> Self {
+        Self {
+            cmd: Self::name(),
+            epubkey: epubkey.serialize().to_vec(),
+            xcvc,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct BackupResponse {
+    /// AES-128-CTR encrypted backup
+    #[serde(with = "serde_bytes")]
+    pub data: Vec<u8>,
+    /// new nonce value, for NEXT command (not this one), 16 bytes
+    #[serde(with = "serde_bytes")]
+    pub card_nonce: Vec<u8>,
+}
+
+impl ResponseApdu for BackupResponse {
+    fn validate(&self) -> Result<(), Error> {
+        check_field_len("card_nonce", &self.card_nonce, CARD_NONCE_SIZE)?;
+        Ok(())
+    }
+}
+
+impl Debug for BackupResponse {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("BackupResponse")
+            .field("data", &REDACTED)
+            .field("card_nonce", &self.card_nonce.to_lower_hex_string())
+            .finish()
+    }
+}
+
+macro_rules! commands {
+    ($($variant:ident($command:ident => $response:ident),)*) => {
+        /// Any command, for handling commands generically, eg. logging, a REPL or scripting the
+        /// mock transport.
+        #[derive(Clone, Debug)]
+        pub enum Command {
+            $($variant($command),)*
+        }
+
+        /// Response to a `Command`, the variant matches the command's.
+        #[derive(Clone, Debug)]
+        pub enum Response {
+            $($variant($response),)*
+        }
+
+        impl Command {
+            pub fn name(&self) -> String {
+                match self {
+                    $(Command::$variant(_) => $command::name(),)*
+                }
+            }
+
+            pub fn apdu_bytes(&self) -> Result<Vec<u8>, Error> {
+                match self {
+                    $(Command::$variant(command) => command.apdu_bytes(),)*
+                }
+            }
+
+            /// Parse the response APDU to this command.
+            pub fn parse_response(&self, cbor: Vec<u8>) -> Result<Response, Error> {
+                match self {
+                    $(Command::$variant(_) => Ok(Response::$variant($response::from_cbor(cbor)?)),)*
+                }
+            }
+        }
+
+        $(
+            impl From<$command> for Command {
+                fn from(command: $command) -> Self {
+                    Command::$variant(command)
+                }
+            }
+        )*
+    };
+}
+
+commands! {
+    Select(AppletSelect => StatusResponse),
+    Status(StatusCommand => StatusResponse),
+    Read(ReadCommand => ReadResponse),
+    Derive(DeriveCommand => DeriveResponse),
+    Certs(CertsCommand => CertsResponse),
+    Check(CheckCommand => CheckResponse),
+    Nfc(NfcCommand => NfcResponse),
+    Sign(SignCommand => SignResponse),
+    Wait(WaitCommand => WaitResponse),
+    New(NewCommand => NewResponse),
+    Unseal(UnsealCommand => UnsealResponse),
+    Dump(DumpCommand => DumpResponse),
+    Xpub(XpubCommand => XpubResponse),
+    Change(ChangeCommand => ChangeResponse),
+    Backup(BackupCommand => BackupResponse),
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cktap_error_code() {
+        let error = Error::CkTap {
+            error: "rate limited".to_string(),
+            code: 429,
+        };
+        assert_eq!(error.cktap_code(), Some(CkTapErrorCode::RateLimited));
+        assert_eq!(CkTapErrorCode::RateLimited.code(), 429);
+        assert_eq!(CkTapErrorCode::from_code(999), None);
+    }
+
+    #[test]
+    fn test_build_apdu_short() {
+        let command = vec![0xa1; 255];
+        let apdu = build_apdu(&CBOR_CLA_INS_P1P2, &command).unwrap();
+        assert_eq!(apdu[..5], [0x00, 0xCB, 0x00, 0x00, 0xff]);
+        assert_eq!(apdu[5..], command[..]);
+    }
+
+    #[test]
+    fn test_build_apdu_extended() {
+        let command = vec![0xa1; 300];
+        let apdu = build_apdu(&CBOR_CLA_INS_P1P2, &command).unwrap();
+        assert_eq!(apdu[..7], [0x00, 0xCB, 0x00, 0x00, 0x00, 0x01, 0x2c]);
+        assert_eq!(apdu[7..], command[..]);
+    }
+
+    #[test]
+    fn test_build_apdu_too_long() {
+        let command = vec![0xa1; u16::MAX as usize + 1];
+        let result = build_apdu(&CBOR_CLA_INS_P1P2, &command);
+        assert!(matches!(result, Err(Error::ApduTooLong { len: 65536 })));
+    }
+
+    #[test]
+    fn test_bitcoin_errors() {
+        let error = Error::from(bitcoin::bip32::Error::InvalidChildNumber(1 << 31));
+        assert!(matches!(error, Error::InvalidPath(_)));
+        let error = Error::from(bitcoin::bip32::Error::WrongExtendedKeyLength(77));
+        assert!(matches!(error, Error::InvalidXpub(_)));
+        let error = Error::from(bitcoin::address::Error::UncompressedPubkey);
+        assert!(matches!(error, Error::InvalidAddress(_)));
+    }
+
+    #[test]
+    fn test_secp256k1_error() {
+        let error = Error::from(PublicKey::from_slice(&[2; 3]).unwrap_err());
+        assert_eq!(
+            error.to_string(),
+            "Secp256k1 error: InvalidPublicKey, malformed public key"
+        );
+    }
+
+    #[test]
+    fn test_ct_eq() {
+        assert!(ct_eq(&[1, 2, 3], &[1, 2, 3]));
+        assert!(!ct_eq(&[1, 2, 3], &[1, 2, 4]));
+        assert!(!ct_eq(&[1, 2, 3], &[1, 2]));
+        assert!(ct_eq(&[], &[]));
+    }
+
+    #[test]
+    fn test_certs_response_from_cbor() {
+        let certs = vec![vec![0x27; 65], vec![0x28; 65]];
+        let value = Value::Map(vec![(
+            Value::Text("cert_chain".to_string()),
+            Value::Array(certs.iter().cloned().map(Value::Bytes).collect()),
+        )]);
+        let mut cbor = Vec::new();
+        into_writer(&value, &mut cbor).unwrap();
+
+        let response = CertsResponse::from_cbor(cbor).unwrap();
+        assert_eq!(response.cert_chain(), certs);
+    }
+
+    #[test]
+    fn test_strip_status_word() {
+        assert_eq!(
+            strip_status_word(vec![0xa0, 0x90, 0x00]).unwrap(),
+            vec![0xa0]
+        );
+        assert!(matches!(
+            strip_status_word(vec![0x6a, 0x82]),
+            Err(Error::ApduStatus {
+                sw1: 0x6a,
+                sw2: 0x82
+            })
+        ));
+        assert!(matches!(
+            strip_status_word(vec![0x90]),
+            Err(Error::Transport(_))
+        ));
+    }
+
+    #[test]
+    fn test_response_field_len() {
+        let read_response = |sig_len: usize| {
+            let value = Value::Map(vec![
+                (
+                    Value::Text("sig".to_string()),
+                    Value::Bytes(vec![1; sig_len]),
+                ),
+                (Value::Text("pubkey".to_string()), Value::Bytes(vec![2; 33])),
+                (
+                    Value::Text("card_nonce".to_string()),
+                    Value::Bytes(vec![3; CARD_NONCE_SIZE]),
+                ),
+            ]);
+            let mut cbor = Vec::new();
+            into_writer(&value, &mut cbor).unwrap();
+            ReadResponse::from_cbor(cbor)
+        };
+        assert!(read_response(64).is_ok());
+        assert!(matches!(
+            read_response(63),
+            Err(Error::MalformedResponse { field: "sig" })
+        ));
+    }
+
+    #[test]
+    fn test_cert_chain() {
+        let secp = Secp256k1::new();
+        let mut rng = secp256k1::rand::thread_rng();
+        let (_, card_key) = secp.generate_keypair(&mut rng);
+        let (batch_priv, batch_key) = secp.generate_keypair(&mut rng);
+        let (root_priv, root_key) = secp.generate_keypair(&mut rng);
+        let cert = |signer: &SecretKey, signed: &PublicKey| {
+            let md = Message::from_hashed_data::<sha256::Hash>(&signed.serialize());
+            let (rec_id, sig) = secp.sign_ecdsa_recoverable(&md, signer).serialize_compact();
+            let mut cert = vec![39 + rec_id.to_i32() as u8];
+            cert.extend(sig);
+            ByteBuf::from(cert)
+        };
+
+        let response = CertsResponse {
+            cert_chain: vec![cert(&batch_priv, &card_key), cert(&root_priv, &batch_key)],
+        };
+        let chain = response.chain(&card_key).unwrap();
+        assert_eq!(chain.keys(), &[card_key, batch_key, root_key]);
+        assert_eq!(chain.card_key(), &card_key);
+        assert_eq!(chain.batch_key(), Some(&batch_key));
+        assert_eq!(chain.root_key(), &root_key);
+
+        // certificates for another card recover other keys
+        let (_, other_key) = secp.generate_keypair(&mut rng);
+        let chain = response.chain(&other_key).unwrap();
+        assert_ne!(chain.root_key(), &root_key);
+
+        let response = CertsResponse {
+            cert_chain: vec![ByteBuf::from(vec![39; 64])],
+        };
+        assert!(matches!(
+            response.chain(&card_key),
+            Err(Error::IncorrectSignature(_))
+        ));
+    }
+
+    #[test]
+    fn test_sign_response_pubkey() {
+        let secp = Secp256k1::new();
+        let (_, pubkey) = secp.generate_keypair(&mut secp256k1::rand::thread_rng());
+        let mut response = SignResponse {
+            slot: 0,
+            sig: vec![0; 64],
+            pubkey: pubkey.serialize().to_vec(),
+            card_nonce: vec![0; CARD_NONCE_SIZE],
+        };
+        assert_eq!(response.pubkey().unwrap(), pubkey);
+
+        response.pubkey = vec![0x02; 32];
+        assert!(matches!(response.pubkey(), Err(Error::InvalidPublicKey(_))));
+    }
+
+    #[test]
+    fn test_derivation_path() {
+        let hardened = 1 << 31;
+        let path = derivation_path(&[hardened + 84, hardened, hardened, 1]).unwrap();
+        assert_eq!(path.to_string(), "m/84'/0'/0'/1");
+        assert_eq!(derivation_path(&[]).unwrap(), DerivationPath::master());
+
+        // the largest hardened index, one more doesn't fit a BIP-32 index
+        let max = u32::MAX as usize;
+        assert_eq!(
+            derivation_path(&[max]).unwrap().to_string(),
+            "m/2147483647'"
+        );
+        #[cfg(target_pointer_width = "64")]
+        assert!(matches!(
+            derivation_path(&[hardened + 84, max + 1]),
+            Err(Error::InvalidPath(_))
+        ));
+    }
+
+    #[test]
+    fn test_xpub_response_xpub() {
+        // BIP-32 test vector 1, chain m/0'
+        let xpub: Xpub = "xpub68Gmy5EdvgibQVfPdqkBBCHxA5htiqg55crXYuXoQRKfDBFA1WEjWgP6LHhwBZeNK1VTsfTFUHCdrfp1bgwQ9xv5ski8PX9rL2dZXvgGDnw".parse().unwrap();
+        let mut response = XpubResponse {
+            xpub: xpub.encode().to_vec(),
+            card_nonce: vec![0; CARD_NONCE_SIZE],
+        };
+        assert_eq!(response.xpub().unwrap(), xpub);
+
+        response.xpub.truncate(77);
+        assert!(matches!(response.xpub(), Err(Error::InvalidXpub(_))));
+    }
+
+    #[test]
+    fn test_debug_redacts_secrets() {
+        let response = UnsealResponse {
+            slot: 0,
+            privkey: vec![0xab; 32],
+            pubkey: vec![0x02; 33],
+            master_pk: vec![0xcd; 32],
+            chain_code: vec![0x01; 32],
+            card_nonce: vec![0x03; CARD_NONCE_SIZE],
+        };
+        let debug = format!("{:?}", response);
+        assert!(!debug.contains("abab") && !debug.contains("cdcd"));
+        assert!(debug.contains(REDACTED) && debug.contains("0202"));
+        let display = response.to_string();
+        assert!(!display.contains("abab") && !display.contains("cdcd"));
+
+        let command = UnsealCommand::new(0, vec![0x02; 33], vec![0xef; 6]);
+        assert!(!format!("{:?}", command).contains("efef"));
+    }
+
+    #[test]
+    fn test_unseal_bad_keys() {
+        let response = UnsealResponse {
+            slot: 0,
+            privkey: vec![0; 32],
+            pubkey: vec![0x05; 33],
+            master_pk: vec![0xcd; 32],
+            chain_code: vec![0x01; 32],
+            card_nonce: vec![0x03; CARD_NONCE_SIZE],
+        };
+        assert!(matches!(
+            response.privkey(),
+            Err(Error::InvalidPrivateKey(_))
+        ));
+        // Display reports the bad keys instead of panicking
+        let display = response.to_string();
+        assert!(display.contains("privkey: Invalid private key"));
+        assert!(display.contains("pubkey: Invalid public key"));
+    }
+
+    #[test]
+    fn test_nfc_url() {
+        let response = NfcResponse {
+            url: "getsatscard.com/start#u=S&o=0&r=a5x2tplf&n=7664168a4ef7b8e8&s=42b209c8"
+                .to_string(),
+            card_nonce: None,
+        };
+        let url = response.url().unwrap();
+        assert_eq!(url.host, "getsatscard.com");
+        assert_eq!(url.path, "/start");
+        assert_eq!(url.param("u"), Some("S"));
+        assert_eq!(url.param("n"), Some("7664168a4ef7b8e8"));
+        assert_eq!(url.param("x"), None);
+        assert_eq!(url.to_string(), format!("https://{}", response.url));
+
+        assert!(NfcUrl::parse("#t=1").is_err());
+    }
+
+    #[test]
+    fn test_read_response_verify() {
+        let secp = Secp256k1::new();
+        let (privkey, pubkey) = secp.generate_keypair(&mut secp256k1::rand::thread_rng());
+        let card_nonce = [1u8; CARD_NONCE_SIZE];
+        let app_nonce = [2u8; USER_NONCE_SIZE];
+        let message_bytes = [b"OPENDIME".as_slice(), &card_nonce, &app_nonce, &[0]].concat();
+        let message = Message::from_hashed_data::<sha256::Hash>(&message_bytes);
+        let sig = secp.sign_ecdsa(&message, &privkey);
+
+        let response = ReadResponse {
+            sig: sig.serialize_compact().to_vec(),
+            pubkey: pubkey.serialize().to_vec(),
+            card_nonce: vec![3u8; CARD_NONCE_SIZE],
+        };
+        let verified = response.verify(&card_nonce, &app_nonce, 0, None).unwrap();
+        assert_eq!(verified, pubkey);
+
+        // signature over a different slot must not verify
+        let result = response.verify(&card_nonce, &app_nonce, 1, None);
+        assert!(matches!(result, Err(Error::BadSignature(_))));
+    }
+}
@@ -0,0 +1,94 @@
+//! Async support for tokio based NFC stacks.
+//!
+//! Transports that are natively async implement `AsyncCkTransport`. Blocking transports, like
+//! `pcsc::Card`, can be shared with async code by wrapping them in an `Arc`, their APDU exchange is
+//! then run on tokio's blocking thread pool.
+//!
+//! Card commands are implemented on top of the blocking `CkTransport`, use `AsyncCard` to run them
+//! without blocking the async runtime.
+
+use crate::apdu::{CommandApdu, Error, ResponseApdu};
+use crate::commands::CkTransport;
+use crate::CkTapCard;
+use serde::de::DeserializeOwned;
+use std::fmt::Debug;
+use std::future::Future;
+use std::sync::Arc;
+
+pub trait AsyncCkTransport: Sized + Sync {
+    fn transmit<C, R>(&self, command: C) -> impl Future<Output = Result<R, Error>> + Send
+    where
+        C: CommandApdu + serde::Serialize + Debug + Send,
+        R: ResponseApdu + DeserializeOwned + Debug,
+    {
+        async move {
+            let command_apdu = command.apdu_bytes()?;
+            let rapdu = self.transmit_apdu(command_apdu).await?;
+            R::from_cbor(rapdu)
+        }
+    }
+
+    fn transmit_apdu(
+        &self,
+        command_apdu: Vec<u8>,
+    ) -> impl Future<Output = Result<Vec<u8>, Error>> + Send;
+}
+
+impl<T> AsyncCkTransport for Arc<T>
+where
+    T: CkTransport + Send + Sync + 'static,
+{
+    fn transmit_apdu(
+        &self,
+        command_apdu: Vec<u8>,
+    ) -> impl Future<Output = Result<Vec<u8>, Error>> + Send {
+        let transport = Arc::clone(self);
+        async move {
+            tokio::task::spawn_blocking(move || {
+                CkTransport::transmit_apdu(transport.as_ref(), command_apdu)
+            })
+            .await
+            .map_err(|e| Error::Tokio(e.to_string()))?
+        }
+    }
+}
+
+/// Runs card commands on tokio's blocking thread pool.
+pub struct AsyncCard<T: CkTransport> {
+    card: Option<CkTapCard<T>>,
+}
+
+impl<T> AsyncCard<T>
+where
+    T: CkTransport + Send + 'static,
+{
+    pub fn new(card: CkTapCard<T>) -> Self {
+        AsyncCard { card: Some(card) }
+    }
+
+    /// Run a closure with the card on the blocking thread pool, eg. to call `read`, `sign` or any
+    /// other card command.
+    pub async fn run<F, R>(&mut self, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut CkTapCard<T>) -> Result<R, Error> + Send + 'static,
+        R: Send + 'static,
+    {
+        let mut card = self
+            .card
+            .take()
+            .ok_or_else(|| Error::Tokio("Card was lost by a failed task".to_string()))?;
+        let (card, result) = tokio::task::spawn_blocking(move || {
+            let result = f(&mut card);
+            (card, result)
+        })
+        .await
+        .map_err(|e| Error::Tokio(e.to_string()))?;
+        self.card = Some(card);
+        result
+    }
+
+    /// Returns the card, or `None` if it was lost by a task that panicked.
+    pub fn into_inner(self) -> Option<CkTapCard<T>> {
+        self.card
+    }
+}
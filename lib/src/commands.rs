@@ -1,17 +1,35 @@
-use crate::factory_root_key::FactoryRootKey;
-use crate::{apdu::*, rand_nonce};
-use crate::{CkTapCard, SatsCard, TapSigner};
+use crate::apdu::*;
+#[cfg(feature = "std")]
+use crate::factory_root_key::{factory_root_keys, factory_root_pubkey, FactoryRootKey};
+#[cfg(feature = "std")]
+use crate::{rand_nonce, CardKind, CkTapCard, SatsCard, TapSigner, CVC_LENGTH};
 
 use secp256k1::ecdh::SharedSecret;
-use secp256k1::ecdsa::{RecoverableSignature, RecoveryId, Signature};
+#[cfg(feature = "std")]
+use secp256k1::ecdsa::Signature;
 use secp256k1::hashes::{sha256, Hash};
-use secp256k1::{rand, All, Message, PublicKey, Secp256k1, SecretKey};
+#[cfg(feature = "std")]
+use secp256k1::rand::{self, RngCore};
+#[cfg(feature = "std")]
+use secp256k1::Message;
+use secp256k1::{All, PublicKey, Secp256k1, SecretKey};
 
-use std::convert::TryFrom;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::fmt::Debug;
+#[cfg(feature = "std")]
+use core::marker::PhantomData;
+#[cfg(feature = "std")]
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::sync::{Arc, Mutex};
+use zeroize::Zeroizing;
 
-use std::fmt::Debug;
+/// Incorrect CVC attempts the card allows before it rate limits authentication.
+pub const CVC_ATTEMPTS: usize = 3;
 
 // Helper functions for authenticated commands.
+#[cfg(feature = "std")]
 pub trait Authentication<T: CkTransport> {
     fn secp(&self) -> &Secp256k1<All>;
     fn pubkey(&self) -> &PublicKey;
@@ -19,70 +37,442 @@ pub trait Authentication<T: CkTransport> {
     fn set_card_nonce(&mut self, new_nonce: Vec<u8>);
     fn auth_delay(&self) -> &Option<usize>;
     fn set_auth_delay(&mut self, auth_delay: Option<usize>);
+    fn bad_cvc_count(&self) -> usize;
+    fn set_bad_cvc_count(&mut self, bad_cvc_count: usize);
+
+    fn session_keys(&self) -> Option<&SessionKeys>;
+    fn set_session_keys(&mut self, session_keys: SessionKeys);
 
     fn transport(&self) -> &T;
+    fn rng(&self) -> &CardRng;
+
+    /// A fresh app nonce from the card's `rng`.
+    fn app_nonce(&self) -> Vec<u8> {
+        self.rng().with(|rng| rand_nonce(rng).to_vec())
+    }
+
+    /// The pending auth delay as a duration, each `wait` takes about one second and counts it down
+    /// by one.
+    fn auth_delay_duration(&self) -> Option<Duration> {
+        self.auth_delay()
+            .map(|auth_delay| Duration::from_secs(auth_delay as u64))
+    }
+
+    /// CVC attempts left before the card starts rate limiting. The card does not report this, so
+    /// it is counted from the bad CVC errors seen by `Wait::authenticated`.
+    fn cvc_tries_left(&self) -> usize {
+        CVC_ATTEMPTS.saturating_sub(self.bad_cvc_count())
+    }
+
+    /// Store the card nonce from a response for use by the next command. The card picks a new
+    /// nonce after every command, so a response that repeats the current nonce is stale or
+    /// replayed and is rejected.
+    fn update_card_nonce(&mut self, new_nonce: Vec<u8>) -> Result<(), Error> {
+        if new_nonce.len() != CARD_NONCE_SIZE {
+            return Err(Error::MalformedResponse {
+                field: "card_nonce",
+            });
+        }
+        if ct_eq(&new_nonce, self.card_nonce()) {
+            return Err(Error::NonceReuse(
+                "Card responded with the nonce already used for this command".to_string(),
+            ));
+        }
+        self.set_card_nonce(new_nonce);
+        Ok(())
+    }
+
+    /// Send a hand built command APDU and return the raw response, for debugging and for commands
+    /// this crate doesn't implement yet. A `card_nonce` in the response is stored for the next
+    /// command.
+    fn transmit_raw(&mut self, apdu: &[u8]) -> Result<Vec<u8>, Error> {
+        let response = self.transport().transmit_apdu(apdu.to_vec())?;
+        let card_nonce = ciborium::de::from_reader::<ciborium::value::Value, _>(&response[..])
+            .ok()
+            .and_then(|value| value.into_map().ok())
+            .and_then(|entries| {
+                entries
+                    .into_iter()
+                    .find_map(|(key, value)| match key.as_text() {
+                        Some("card_nonce") => value.into_bytes().ok(),
+                        _ => None,
+                    })
+            });
+        if let Some(card_nonce) = card_nonce {
+            self.update_card_nonce(card_nonce)?;
+        }
+        Ok(response)
+    }
+
+    /// Build the APDU of an authenticated command without sending it, for auditing or test
+    /// vectors. The xcvc is computed against the current card nonce with the session's ephemeral
+    /// key, so the bytes match what the next command would send. `build` gets the ephemeral
+    /// pubkey and xcvc, eg. `|epubkey, xcvc| Ok(XpubCommand::new(false, epubkey, xcvc))`.
+    fn build_command_bytes<C, F>(&mut self, cvc: String, build: F) -> Result<Vec<u8>, Error>
+    where
+        Self: Sized,
+        C: CommandApdu + serde::Serialize + Debug,
+        F: FnOnce(PublicKey, Vec<u8>) -> Result<C, Error>,
+    {
+        let used_nonce = self.session_keys().and_then(|keys| keys.used_nonce.clone());
+        let (_, epubkey, xcvc) = self.calc_ekeys_xcvc(cvc, &C::name())?;
+        // nothing is sent, the nonce is still unused
+        set_used_nonce(self, used_nonce);
+        build(epubkey, xcvc)?.apdu_bytes()
+    }
+
+    /// The CVC is checked with `check_cvc` and zeroized once it has been encrypted. The ephemeral
+    /// key pair is picked on the first authenticated command and reused while the card accepts
+    /// the commands, only the xcvc changes as the card nonce rotates. A card error picks a new key
+    /// pair, see `transmit_auth`.
+    ///
+    /// Fails with `Error::NonceReuse` if the current card nonce was already used for a command,
+    /// eg. after a command that failed before the card answered with a new one. A `status` learns
+    /// the card's current nonce.
+    fn calc_ekeys_xcvc(
+        &mut self,
+        cvc: String,
+        command: &str,
+    ) -> Result<(SecretKey, PublicKey, Vec<u8>), Error> {
+        let cvc = Zeroizing::new(cvc);
+        check_cvc(&cvc)?;
+        if self.session_keys().is_none() {
+            new_session_keys(self);
+        }
+        let mut keys = self.session_keys().expect("session keys are set").clone();
+        if self.card_nonce().len() != CARD_NONCE_SIZE {
+            return Err(Error::InvalidNonce(format!(
+                "Card nonce must be {} bytes, got {}, refresh it with status",
+                CARD_NONCE_SIZE,
+                self.card_nonce().len()
+            )));
+        }
+        if let Some(used_nonce) = &keys.used_nonce {
+            if ct_eq(used_nonce, self.card_nonce()) {
+                return Err(Error::NonceReuse(
+                    "Card nonce was already used for a command, refresh it with status".to_string(),
+                ));
+            }
+        }
+        let xcvc = encrypt_cvc(&keys.session_key[..], command, self.card_nonce(), &cvc);
+        keys.used_nonce = Some(self.card_nonce().clone());
+        let (eprivkey, epubkey) = (keys.eprivkey, keys.epubkey);
+        self.set_session_keys(keys);
+        Ok((eprivkey, epubkey, xcvc))
+    }
+}
 
-    fn calc_ekeys_xcvc(&self, cvc: String, command: &str) -> (SecretKey, PublicKey, Vec<u8>) {
-        let secp = Self::secp(self);
-        let pubkey = Self::pubkey(self);
-        let nonce = Self::card_nonce(self);
-        let cvc_bytes = cvc.as_bytes();
-        let card_nonce_bytes = nonce.as_slice();
-        let card_nonce_command = [card_nonce_bytes, command.as_bytes()].concat();
-        let (eprivkey, epubkey) = secp.generate_keypair(&mut rand::thread_rng());
-        let session_key = SharedSecret::new(pubkey, &eprivkey);
+/// Send an authenticated command. The card keeps its nonce when it answers with an error, so the
+/// nonce can be used again for a retry, any other failure leaves it marked as used. After a card
+/// error the session keys are replaced, so the retry is sent with a new ephemeral key and xcvc
+/// rather than the exact bytes the card just rejected.
+#[cfg(feature = "std")]
+pub(crate) fn transmit_auth<'a, T, A, C, R>(card: &mut A, command: C) -> Result<R, Error>
+where
+    T: CkTransport,
+    A: Authentication<T> + ?Sized,
+    C: CommandApdu + serde::Serialize + Debug,
+    R: ResponseApdu + serde::Deserialize<'a> + Debug,
+{
+    let result = card.transport().transmit(command);
+    if let Err(Error::CkTap { .. }) = &result {
+        new_session_keys(card);
+    }
+    result
+}
+
+#[cfg(feature = "std")]
+fn new_session_keys<T: CkTransport, C: Authentication<T> + ?Sized>(card: &mut C) {
+    let session_keys = card
+        .rng()
+        .with(|rng| SessionKeys::new(card.secp(), card.pubkey(), rng));
+    card.set_session_keys(session_keys);
+}
+
+#[cfg(feature = "std")]
+fn set_used_nonce<T: CkTransport, C: Authentication<T> + ?Sized>(
+    card: &mut C,
+    used_nonce: Option<Vec<u8>>,
+) {
+    if let Some(mut keys) = card.session_keys().cloned() {
+        keys.used_nonce = used_nonce;
+        card.set_session_keys(keys);
+    }
+}
+
+/// Check a CVC before it is encrypted, saving a round trip to the card for one it would reject.
+/// The protocol takes 6 to 32 bytes, a SATSCARD CVC is the 6 digits printed on it while a
+/// TAPSIGNER CVC can be changed to a longer one.
+#[cfg(feature = "std")]
+pub fn check_cvc(cvc: &str) -> Result<(), Error> {
+    if !CVC_LENGTH.contains(&cvc.len()) {
+        return Err(Error::InvalidCvcLength { len: cvc.len() });
+    }
+    if !cvc.bytes().all(|b| b.is_ascii() && !b.is_ascii_control()) {
+        return Err(Error::InvalidCvc("CVC must be printable ASCII".to_string()));
+    }
+    Ok(())
+}
 
-        let md = sha256::Hash::hash(card_nonce_command.as_slice());
+/// Random source for app nonces and the session's ephemeral key, `thread_rng` unless a card is
+/// given a fixed generator with `with_rng`, eg. a `StepRng` to make the commands it sends
+/// reproducible in tests. Clones of a card share the generator.
+#[cfg(feature = "std")]
+#[derive(Clone, Default)]
+pub struct CardRng(Option<Arc<Mutex<dyn RngCore + Send>>>);
 
-        let mask: Vec<u8> = session_key
-            .as_ref()
+#[cfg(feature = "std")]
+impl CardRng {
+    pub fn new<R: RngCore + Send + 'static>(rng: R) -> Self {
+        CardRng(Some(Arc::new(Mutex::new(rng))))
+    }
+
+    /// Run `f` with the generator.
+    pub fn with<X>(&self, f: impl FnOnce(&mut dyn RngCore) -> X) -> X {
+        match &self.0 {
+            Some(rng) => f(&mut *rng.lock().unwrap_or_else(|e| e.into_inner())),
+            None => f(&mut rand::thread_rng()),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Debug for CardRng {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match &self.0 {
+            Some(_) => write!(f, "CardRng(custom)"),
+            None => write!(f, "CardRng(thread_rng)"),
+        }
+    }
+}
+
+/// Ephemeral key pair and ECDH session key for the authenticated commands of one card session.
+/// Saves a key pair generation and an ECDH per command, measured at about 90µs on a desktop CPU
+/// and more on phones. Small against an NFC round trip, but it adds up over multi-command flows
+/// like `SatsCard::dump_all_slots`. The keys are replaced when the card answers an error, so the
+/// retry of a rejected command never repeats its xcvc, and are erased on drop.
+#[cfg(feature = "std")]
+#[derive(Clone)]
+pub struct SessionKeys {
+    eprivkey: SecretKey,
+    epubkey: PublicKey,
+    session_key: Zeroizing<[u8; 32]>,
+    // card nonce the last xcvc was computed against
+    used_nonce: Option<Vec<u8>>,
+}
+
+#[cfg(feature = "std")]
+impl SessionKeys {
+    fn new(secp: &Secp256k1<All>, card_pubkey: &PublicKey, rng: &mut dyn RngCore) -> Self {
+        let (eprivkey, epubkey) = secp.generate_keypair(rng);
+        let session_key = Zeroizing::new(SharedSecret::new(card_pubkey, &eprivkey).secret_bytes());
+        SessionKeys {
+            eprivkey,
+            epubkey,
+            session_key,
+            used_nonce: None,
+        }
+    }
+
+    /// The ECDH shared secret, used to decrypt the secrets the card returns.
+    pub fn session_key(&self) -> &[u8; 32] {
+        &self.session_key
+    }
+}
+
+#[cfg(feature = "std")]
+impl Drop for SessionKeys {
+    fn drop(&mut self) {
+        self.eprivkey.non_secure_erase();
+    }
+}
+
+/// Encrypt a CVC for an authenticated command.
+///
+/// The session key is the ECDH shared secret between the app's ephemeral private key and the
+/// card's public key (SHA-256 of the compressed shared point). The CVC is encrypted as:
+///
+/// xcvc = cvc XOR (session_key XOR SHA-256(card_nonce || command name))
+///
+/// truncated to the length of the CVC. Returns the app's ephemeral public key and the xcvc.
+pub fn calc_xcvc(
+    secp: &Secp256k1<All>,
+    command: &str,
+    card_nonce: &[u8],
+    card_pubkey: &PublicKey,
+    eprivkey: &SecretKey,
+    cvc: &str,
+) -> (PublicKey, Vec<u8>) {
+    let epubkey = PublicKey::from_secret_key(secp, eprivkey);
+    let session_key = Zeroizing::new(SharedSecret::new(card_pubkey, eprivkey).secret_bytes());
+    (
+        epubkey,
+        encrypt_cvc(&session_key[..], command, card_nonce, cvc),
+    )
+}
+
+fn encrypt_cvc(session_key: &[u8], command: &str, card_nonce: &[u8], cvc: &str) -> Vec<u8> {
+    let card_nonce_command = [card_nonce, command.as_bytes()].concat();
+    let md = sha256::Hash::hash(card_nonce_command.as_slice());
+
+    let cvc_bytes = cvc.as_bytes();
+    let mask: Zeroizing<Vec<u8>> = Zeroizing::new(
+        session_key
             .iter()
-            .zip(md.as_ref())
+            .zip(md.as_byte_array())
             .map(|(x, y)| x ^ y)
             .take(cvc_bytes.len())
-            .collect();
-        let xcvc = cvc_bytes.iter().zip(mask).map(|(x, y)| x ^ y).collect();
-        (eprivkey, epubkey, xcvc)
-    }
+            .collect(),
+    );
+    cvc_bytes
+        .iter()
+        .zip(mask.iter())
+        .map(|(x, y)| x ^ y)
+        .collect()
 }
 
 pub trait CkTransport: Sized {
+    /// With the `tracing` feature each exchange is logged with the command name and APDU lengths,
+    /// never the command or response contents as they may carry CVCs or keys.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(command = %C::name()), err(Display))
+    )]
     fn transmit<'a, C, R>(&self, command: C) -> Result<R, Error>
     where
         C: CommandApdu + serde::Serialize + Debug,
         R: ResponseApdu + serde::Deserialize<'a> + Debug,
     {
-        let command_apdu = command.apdu_bytes();
+        let command_apdu = command.apdu_bytes()?;
+        #[cfg(feature = "tracing")]
+        tracing::trace!(len = command_apdu.len(), "command apdu");
         let rapdu = self.transmit_apdu(command_apdu)?;
+        #[cfg(feature = "tracing")]
+        tracing::trace!(len = rapdu.len(), "response apdu");
         let response = R::from_cbor(rapdu.to_vec())?;
         Ok(response)
     }
+    /// Send a command APDU and return the CBOR response body, a trailing status word must be
+    /// checked and removed, see `strip_status_word`.
     fn transmit_apdu(&self, command_apdu: Vec<u8>) -> Result<Vec<u8>, Error>;
 
+    /// Send any `Command` and parse its `Response`. Unlike the card methods this does not track
+    /// the card nonce.
+    fn transmit_command(&self, command: &Command) -> Result<Response, Error> {
+        let rapdu = self.transmit_apdu(command.apdu_bytes()?)?;
+        command.parse_response(rapdu)
+    }
+
+    /// Select the applet and connect to the card, cards with a protocol version newer than
+    /// `SUPPORTED_PROTO` are rejected with `Error::UnsupportedProtocol`.
+    #[cfg(feature = "std")]
     fn to_cktap(self) -> Result<CkTapCard<Self>, Error> {
         // Get status from card
         let cmd = AppletSelect::default();
         let status_response: StatusResponse = self.transmit(cmd)?;
+        if status_response.proto > SUPPORTED_PROTO {
+            return Err(Error::UnsupportedProtocol {
+                got: status_response.proto,
+                supported: SUPPORTED_PROTO,
+            });
+        }
+        card_from_status(self, status_response)
+    }
+
+    /// Like `to_cktap` but proceeds with cards using a newer protocol version, at the risk of
+    /// misreading their responses.
+    #[cfg(feature = "std")]
+    fn to_cktap_any_proto(self) -> Result<CkTapCard<Self>, Error> {
+        let status_response: StatusResponse = self.transmit(AppletSelect::default())?;
+        card_from_status(self, status_response)
+    }
+
+    /// Prove the card is genuine without its CVC and return the factory certified card pubkey,
+    /// for a third party checking an embedded SATSCHIP or any card it doesn't operate. Only the
+    /// applet select, `status`, `certs` and `check` are sent, see `Certificate::verify_card`.
+    #[cfg(feature = "std")]
+    fn verify_genuine(self) -> Result<PublicKey, Error> {
+        self.verify_genuine_with_roots(&[factory_root_pubkey()])
+    }
+
+    /// Same as `verify_genuine` but trusting `roots`, see `Certificate::verify_card_with_roots`.
+    #[cfg(feature = "std")]
+    fn verify_genuine_with_roots(self, roots: &[PublicKey]) -> Result<PublicKey, Error> {
+        match self.to_cktap()? {
+            CkTapCard::SatsCard(sc) => Ok(sc.verify_card_with_roots(roots)?.pubkey),
+            CkTapCard::TapSigner(ts) | CkTapCard::SatsChip(ts) => {
+                Ok(ts.verify_card_with_roots(roots)?.pubkey)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+fn card_from_status<T: CkTransport>(
+    transport: T,
+    status_response: StatusResponse,
+) -> Result<CkTapCard<T>, Error> {
+    match status_response.card_type()? {
+        CardKind::SatsChip => Ok(CkTapCard::SatsChip(TapSigner::from_status(
+            transport,
+            status_response,
+        )?)),
+        CardKind::TapSigner => Ok(CkTapCard::TapSigner(TapSigner::from_status(
+            transport,
+            status_response,
+        )?)),
+        CardKind::SatsCard => Ok(CkTapCard::SatsCard(SatsCard::from_status(
+            transport,
+            status_response,
+        )?)),
+    }
+}
+
+/// Transport that sends command APDUs through a closure, for plugging in a test harness, a network
+/// forwarded reader or an FFI callback without implementing `CkTransport` by hand.
+#[derive(Clone)]
+pub struct FnTransport<F> {
+    transmit: RefCell<F>,
+}
 
-        // Return correct card variant using status
-        match (status_response.tapsigner, status_response.satschip) {
-            (Some(true), None) => Ok(CkTapCard::TapSigner(TapSigner::from_status(
-                self,
-                status_response,
-            ))),
-            (Some(true), Some(true)) => Ok(CkTapCard::TapSigner(TapSigner::from_status(
-                self,
-                status_response,
-            ))),
-            (None, None) => Ok(CkTapCard::SatsCard(
-                SatsCard::from_status(self, status_response).unwrap(),
-            )),
-            (_, _) => Err(Error::UnknownCardType("Card not recognized.".to_string())),
+impl<F> FnTransport<F>
+where
+    F: FnMut(&[u8]) -> Result<Vec<u8>, Error>,
+{
+    /// Send each command APDU with `transmit`, which returns the card's response APDU.
+    pub fn from_fn(transmit: F) -> Self {
+        FnTransport {
+            transmit: RefCell::new(transmit),
         }
     }
 }
 
+impl<F> CkTransport for FnTransport<F>
+where
+    F: FnMut(&[u8]) -> Result<Vec<u8>, Error>,
+{
+    fn transmit_apdu(&self, command_apdu: Vec<u8>) -> Result<Vec<u8>, Error> {
+        (self.transmit.borrow_mut())(&command_apdu)
+    }
+}
+
+/// A borrowed transport, so one reader can serve card sessions in turn without being moved into
+/// the card, eg. a kiosk loop calling `(&mut transport).to_cktap()` for each tapped card.
+impl<T: CkTransport> CkTransport for &T {
+    fn transmit_apdu(&self, command_apdu: Vec<u8>) -> Result<Vec<u8>, Error> {
+        (**self).transmit_apdu(command_apdu)
+    }
+}
+
+/// See the impl for `&T`.
+impl<T: CkTransport> CkTransport for &mut T {
+    fn transmit_apdu(&self, command_apdu: Vec<u8>) -> Result<Vec<u8>, Error> {
+        (**self).transmit_apdu(command_apdu)
+    }
+}
+
 // card traits
+#[cfg(feature = "std")]
 pub trait Read<T>: Authentication<T>
 where
     T: CkTransport,
@@ -90,31 +480,31 @@ where
     fn requires_auth(&self) -> bool;
     fn slot(&self) -> Option<u8>;
 
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
     fn read(&mut self, cvc: Option<String>) -> Result<ReadResponse, Error> {
         let card_nonce = self.card_nonce().clone();
-        let app_nonce = rand_nonce();
+        let app_nonce = self.app_nonce();
 
         let (cmd, session_key) = if self.requires_auth() {
-            let (eprivkey, epubkey, xcvc) =
-                self.calc_ekeys_xcvc(cvc.unwrap(), &ReadCommand::name());
+            let cvc = cvc.ok_or_else(|| Error::InvalidCvc("CVC required".to_string()))?;
+            let (eprivkey, epubkey, xcvc) = self.calc_ekeys_xcvc(cvc, &ReadCommand::name())?;
             (
-                ReadCommand::authenticated(app_nonce.clone(), epubkey, xcvc),
+                ReadCommand::authenticated(app_nonce.clone(), epubkey, xcvc)?,
                 Some(SharedSecret::new(self.pubkey(), &eprivkey)),
             )
         } else {
-            (ReadCommand::unauthenticated(app_nonce.clone()), None)
+            (ReadCommand::unauthenticated(app_nonce.clone())?, None)
         };
 
-        let read_response: Result<ReadResponse, Error> = self.transport().transmit(cmd);
-        if let Ok(response) = &read_response {
-            self.secp().verify_ecdsa(
-                &self.message_digest(card_nonce, app_nonce),
-                &response.signature()?, // or add 'from' trait: Signature::from(response.sig: )
-                &response.pubkey(session_key),
-            )?;
-            self.set_card_nonce(response.card_nonce.clone());
-        }
-        read_response
+        let read_response: ReadResponse = transmit_auth(self, cmd)?;
+        self.update_card_nonce(read_response.card_nonce.clone())?;
+        read_response.verify(
+            &card_nonce,
+            &app_nonce,
+            self.slot().unwrap_or(0),
+            session_key,
+        )?;
+        Ok(read_response)
     }
 
     fn message_digest(&self, card_nonce: Vec<u8>, app_nonce: Vec<u8>) -> Message {
@@ -131,23 +521,27 @@ where
     }
 }
 
+#[cfg(feature = "std")]
 pub trait Wait<T>: Authentication<T>
 where
     T: CkTransport,
 {
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
     fn wait(&mut self, cvc: Option<String>) -> Result<WaitResponse, Error> {
-        let epubkey_xcvc = cvc.map(|cvc| {
-            let (_, epubkey, xcvc) = self.calc_ekeys_xcvc(cvc, &WaitCommand::name());
-            (epubkey, xcvc)
-        });
-
-        let (epubkey, xcvc) = epubkey_xcvc
-            .map(|(epubkey, xcvc)| (Some(epubkey.serialize().to_vec()), Some(xcvc)))
-            .unwrap_or((None, None));
+        let (epubkey, xcvc) = match cvc {
+            Some(cvc) => {
+                let (_, epubkey, xcvc) = self.calc_ekeys_xcvc(cvc, &WaitCommand::name())?;
+                (Some(epubkey.serialize().to_vec()), Some(xcvc))
+            }
+            None => (None, None),
+        };
 
         let wait_command = WaitCommand::new(epubkey, xcvc);
-        let wait_response: Result<WaitResponse, Error> = self.transport().transmit(wait_command);
+        let wait_response: Result<WaitResponse, Error> = transmit_auth(self, wait_command);
         if let Ok(response) = &wait_response {
+            if let Some(card_nonce) = &response.card_nonce {
+                self.update_card_nonce(card_nonce.clone())?;
+            }
             if response.auth_delay > 0 {
                 self.set_auth_delay(Some(response.auth_delay));
             } else {
@@ -156,48 +550,286 @@ where
         }
         wait_response
     }
+
+    /// Wait out the rate-limit delay, each `wait` command takes about a second on the card.
+    /// `progress` is called with the remaining delay in seconds before each `wait`.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    fn clear_auth_delay<F: FnMut(usize)>(&mut self, mut progress: F) -> Result<(), Error> {
+        while let Some(auth_delay) = *self.auth_delay() {
+            progress(auth_delay);
+            self.wait(None)?;
+        }
+        Ok(())
+    }
+
+    /// Check a CVC with a `wait` command, the card takes it without side effects while there is
+    /// no auth delay. Returns `false` for a wrong CVC, which still counts towards the rate limit.
+    /// Fails with `Error::AuthDelayPending` rather than sending the CVC while there is a delay.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    fn test_cvc(&mut self, cvc: String) -> Result<bool, Error> {
+        if let Some(auth_delay) = *self.auth_delay() {
+            return Err(Error::AuthDelayPending { auth_delay });
+        }
+        match self.wait(Some(cvc)) {
+            Ok(_) => {
+                self.set_bad_cvc_count(0);
+                Ok(true)
+            }
+            Err(e) if e.cktap_code() == Some(CkTapErrorCode::BadAuth) => {
+                self.set_bad_cvc_count(self.bad_cvc_count() + 1);
+                Ok(false)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Iterator over the remaining delay, each `next` sends one `wait` command and yields the
+    /// `auth_delay` left after it, ending after zero. Yields nothing if there is no delay.
+    fn wait_iter(&mut self) -> WaitIter<'_, T, Self>
+    where
+        Self: Sized,
+    {
+        WaitIter {
+            card: self,
+            done: false,
+            transport: PhantomData,
+        }
+    }
+
+    /// Run an authenticated command with the given CVC.
+    ///
+    /// A bad auth (401) error is returned as `Error::WrongCvc` with the tries left before the card
+    /// rate limits. On a rate limited (429) error the delay is waited out and the command is
+    /// retried once.
+    fn authenticated<R, F>(&mut self, cvc: String, mut f: F) -> Result<R, Error>
+    where
+        Self: Sized,
+        F: FnMut(&mut Self, String) -> Result<R, Error>,
+    {
+        let cvc = Zeroizing::new(cvc);
+        let mut result = f(self, cvc.to_string());
+        if let Err(e) = &result {
+            if e.cktap_code() == Some(CkTapErrorCode::RateLimited) {
+                // learn the required delay, then wait it out
+                self.wait(None)?;
+                self.clear_auth_delay(|_| {})?;
+                result = f(self, cvc.to_string());
+            }
+        }
+        match result {
+            Err(e) if e.cktap_code() == Some(CkTapErrorCode::BadAuth) => {
+                self.set_bad_cvc_count(self.bad_cvc_count() + 1);
+                Err(Error::WrongCvc {
+                    tries_left: self.cvc_tries_left(),
+                })
+            }
+            Ok(r) => {
+                self.set_bad_cvc_count(0);
+                Ok(r)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Countdown returned by `Wait::wait_iter`.
+#[cfg(feature = "std")]
+pub struct WaitIter<'a, T: CkTransport, C: Wait<T>> {
+    card: &'a mut C,
+    done: bool,
+    transport: PhantomData<T>,
+}
+
+#[cfg(feature = "std")]
+impl<T: CkTransport, C: Wait<T>> Iterator for WaitIter<'_, T, C> {
+    type Item = Result<usize, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done || self.card.auth_delay().is_none() {
+            return None;
+        }
+        let result = self.card.wait(None).map(|response| response.auth_delay);
+        self.done = !matches!(result, Ok(auth_delay) if auth_delay > 0);
+        Some(result)
+    }
+}
+
+#[cfg(feature = "std")]
+pub trait Nfc<T>: Authentication<T>
+where
+    T: CkTransport,
+{
+    /// The url the card would give a phone over an NFC tap.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    fn nfc(&mut self) -> Result<NfcUrl, Error> {
+        let nfc_response: NfcResponse = self.transport().transmit(NfcCommand::default())?;
+        if let Some(card_nonce) = &nfc_response.card_nonce {
+            self.update_card_nonce(card_nonce.clone())?;
+        }
+        nfc_response.url()
+    }
+}
+
+/// Commands every card takes. The trait is object safe and implemented by `CkTapCard`, so code
+/// that doesn't care about the card type can work with a `&mut dyn SharedCommands`. Card specific
+/// commands stay on `SatsCard` and `TapSigner`.
+#[cfg(feature = "std")]
+pub trait SharedCommands {
+    /// Send the status command, see `SatsCard::status` and `TapSigner::status`.
+    fn status(&mut self) -> Result<StatusResponse, Error>;
+    fn wait(&mut self, cvc: Option<String>) -> Result<WaitResponse, Error>;
+    /// The CVC is required for a TAPSIGNER and ignored for a SATSCARD.
+    fn read(&mut self, cvc: Option<String>) -> Result<ReadResponse, Error>;
+    fn nfc(&mut self) -> Result<NfcUrl, Error>;
+    fn card_nonce(&self) -> &[u8];
+    fn auth_delay(&self) -> Option<usize>;
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn refresh_status<T: CkTransport, C: Authentication<T>>(
+    card: &mut C,
+) -> Result<StatusResponse, Error> {
+    let status: StatusResponse = card.transport().transmit(StatusCommand::default())?;
+    card.set_card_nonce(status.card_nonce.clone());
+    set_used_nonce(card, None);
+    card.set_auth_delay(status.auth_delay.filter(|auth_delay| *auth_delay > 0));
+    Ok(status)
 }
 
+#[cfg(feature = "std")]
+impl<T: CkTransport> SharedCommands for CkTapCard<T> {
+    fn status(&mut self) -> Result<StatusResponse, Error> {
+        match self {
+            CkTapCard::SatsCard(sc) => sc.status(),
+            CkTapCard::TapSigner(ts) | CkTapCard::SatsChip(ts) => ts.status(),
+        }
+    }
+
+    fn wait(&mut self, cvc: Option<String>) -> Result<WaitResponse, Error> {
+        match self {
+            CkTapCard::SatsCard(sc) => Wait::wait(sc, cvc),
+            CkTapCard::TapSigner(ts) | CkTapCard::SatsChip(ts) => Wait::wait(ts, cvc),
+        }
+    }
+
+    fn read(&mut self, cvc: Option<String>) -> Result<ReadResponse, Error> {
+        match self {
+            CkTapCard::SatsCard(sc) => Read::read(sc, None),
+            CkTapCard::TapSigner(ts) | CkTapCard::SatsChip(ts) => Read::read(ts, cvc),
+        }
+    }
+
+    fn nfc(&mut self) -> Result<NfcUrl, Error> {
+        match self {
+            CkTapCard::SatsCard(sc) => Nfc::nfc(sc),
+            CkTapCard::TapSigner(ts) | CkTapCard::SatsChip(ts) => Nfc::nfc(ts),
+        }
+    }
+
+    fn card_nonce(&self) -> &[u8] {
+        match self {
+            CkTapCard::SatsCard(sc) => &sc.card_nonce,
+            CkTapCard::TapSigner(ts) | CkTapCard::SatsChip(ts) => &ts.card_nonce,
+        }
+    }
+
+    fn auth_delay(&self) -> Option<usize> {
+        match self {
+            CkTapCard::SatsCard(sc) => sc.auth_delay,
+            CkTapCard::TapSigner(ts) | CkTapCard::SatsChip(ts) => ts.auth_delay,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
 pub trait Certificate<T>: Authentication<T>
 where
     T: CkTransport,
 {
-    fn message_digest(&mut self, card_nonce: Vec<u8>, app_nonce: Vec<u8>) -> Message;
+    fn message_digest(&mut self, card_nonce: Vec<u8>, app_nonce: Vec<u8>)
+        -> Result<Message, Error>;
+
+    /// The card's certificate chain, eg. to see which factory batch signed the card. The card is
+    /// not asked to prove it holds the card key, use `check_certificate` for that.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    fn cert_chain(&mut self) -> Result<CertChain, Error> {
+        let certs_response: CertsResponse = self.transport().transmit(CertsCommand::default())?;
+        certs_response.chain(self.pubkey())
+    }
 
+    /// Check the card signs a fresh nonce with its key and its certificate chain leads to one of
+    /// the published or dev Coinkite factory root keys, returning which one.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
     fn check_certificate(&mut self) -> Result<FactoryRootKey, Error> {
-        let nonce = rand_nonce();
+        let root_key =
+            self.check_certificate_with_roots(&factory_root_keys().map(|root| *root.pubkey()))?;
+        FactoryRootKey::try_from(root_key)
+    }
+
+    /// Same as `check_certificate` but the chain must lead to one of `roots`, eg. a dev root for
+    /// emulator and test cards. Returns the root key the chain leads to.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    fn check_certificate_with_roots(&mut self, roots: &[PublicKey]) -> Result<PublicKey, Error> {
+        let nonce = self.app_nonce();
 
         let card_nonce = self.card_nonce().clone();
 
         let certs_cmd = CertsCommand::default();
         let certs_response: CertsResponse = self.transport().transmit(certs_cmd)?;
 
-        let check_cmd = CheckCommand::new(nonce.clone());
-        let check_response: Result<CheckResponse, Error> = self.transport().transmit(check_cmd);
-        if let Ok(response) = &check_response {
-            self.set_card_nonce(response.card_nonce.clone());
+        let check_cmd = CheckCommand::new(nonce.clone())?;
+        let check_response: CheckResponse = self.transport().transmit(check_cmd)?;
+        self.update_card_nonce(check_response.card_nonce.clone())?;
+
+        self.verify_card_signature(check_response.auth_sig, card_nonce, nonce)?;
+
+        let chain = certs_response.chain(self.pubkey())?;
+        let root_key = *chain.root_key();
+        if !roots
+            .iter()
+            .any(|root| ct_eq(&root.serialize(), &root_key.serialize()))
+        {
+            return Err(Error::IncorrectSignature(
+                "Root cert is not a trusted root key. Card is counterfeit.".to_string(),
+            ));
         }
+        Ok(root_key)
+    }
 
-        self.verify_card_signature(check_response.unwrap().auth_sig, card_nonce, nonce)?;
+    /// Run the full authenticity check, the recommended first step before trusting a card.
+    ///
+    /// Confirms the card still reports the pubkey it was connected with, then checks the card
+    /// signs a fresh nonce with that key and its certificate chain leads to the published Coinkite
+    /// factory root key. Cards certified by the testing root key are rejected.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    fn verify_card(self) -> Result<VerifiedCard<Self>, Error>
+    where
+        Self: Sized,
+    {
+        self.verify_card_with_roots(&[factory_root_pubkey()])
+    }
 
-        let mut pubkey = *self.pubkey();
-        for sig in &certs_response.cert_chain() {
-            // BIP-137: https://github.com/bitcoin/bips/blob/master/bip-0137.mediawiki
-            let subtract_by = match sig[0] {
-                27..=30 => 27, // P2PKH uncompressed
-                31..=34 => 31, // P2PKH compressed
-                35..=38 => 35, // Segwit P2SH
-                39..=42 => 39, // Segwit Bech32
-                _ => panic!("Unrecognized BIP-137 address"),
-            };
-            let rec_id = RecoveryId::from_i32((sig[0] as i32) - subtract_by).unwrap();
-            let (_, sig) = sig.split_at(1);
-            let rec_sig = RecoverableSignature::from_compact(sig, rec_id).unwrap();
-            let md = Message::from_hashed_data::<sha256::Hash>(&pubkey.serialize());
-            pubkey = self.secp().recover_ecdsa(&md, &rec_sig).unwrap();
+    /// Same as `verify_card` but trusting `roots` instead of the production root key, eg. the dev
+    /// root from `factory_root_key::dev_factory_root_pubkey` to test against the emulator. Prefer
+    /// `verify_card` for real cards.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    fn verify_card_with_roots(mut self, roots: &[PublicKey]) -> Result<VerifiedCard<Self>, Error>
+    where
+        Self: Sized,
+    {
+        let status: StatusResponse = self.transport().transmit(StatusCommand::default())?;
+        self.set_card_nonce(status.card_nonce.clone());
+        if !ct_eq(&status.pubkey()?.serialize(), &self.pubkey().serialize()) {
+            return Err(Error::InvalidPublicKey(
+                "Card pubkey does not match its status".to_string(),
+            ));
         }
 
-        FactoryRootKey::try_from(pubkey)
+        let root_key = self.check_certificate_with_roots(roots)?;
+        Ok(VerifiedCard {
+            card: self,
+            root_key,
+        })
     }
 
     fn verify_card_signature(
@@ -205,24 +837,629 @@ where
         signature: Vec<u8>,
         card_nonce: Vec<u8>,
         app_nonce: Vec<u8>,
-    ) -> Result<(), secp256k1::Error> {
-        let message = self.message_digest(card_nonce, app_nonce);
-        let signature = Signature::from_compact(signature.as_slice())
-            .expect("Failed to construct ECDSA signature from check response");
+    ) -> Result<(), Error> {
+        let message = self.message_digest(card_nonce, app_nonce)?;
+        let signature = Signature::from_compact(signature.as_slice())?;
         self.secp()
-            .verify_ecdsa(&message, &signature, self.pubkey())
+            .verify_ecdsa(&message, &signature, self.pubkey())?;
+        Ok(())
+    }
+}
+
+/// A card that passed `Certificate::verify_card`, require this type before trusting a card with
+/// sensitive operations.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct VerifiedCard<C> {
+    card: C,
+    root_key: PublicKey,
+}
+
+#[cfg(feature = "std")]
+impl<C> VerifiedCard<C> {
+    /// The factory root key the card's certificate chain leads to.
+    pub fn root_key(&self) -> &PublicKey {
+        &self.root_key
+    }
+
+    pub fn into_inner(self) -> C {
+        self.card
     }
 }
 
-#[cfg(feature = "emulator")]
-#[cfg(test)]
+#[cfg(feature = "std")]
+impl<C> core::ops::Deref for VerifiedCard<C> {
+    type Target = C;
+
+    fn deref(&self) -> &C {
+        &self.card
+    }
+}
+
+#[cfg(feature = "std")]
+impl<C> core::ops::DerefMut for VerifiedCard<C> {
+    fn deref_mut(&mut self) -> &mut C {
+        &mut self.card
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
 mod tests {
     use super::*;
 
+    #[cfg(feature = "emulator")]
     use crate::emulator::find_emulator;
+    #[cfg(feature = "emulator")]
     use crate::emulator::CVC;
+    #[cfg(feature = "test-utils")]
+    use crate::mock::{
+        cbor_map, expect_satscard, expect_tapsigner, fake_card, mock_satscard, status_with,
+    };
+    #[cfg(feature = "emulator")]
     use crate::rand_chaincode;
 
+    #[test]
+    fn test_calc_xcvc() {
+        let secp = Secp256k1::new();
+        let (card_privkey, card_pubkey) = secp.generate_keypair(&mut rand::thread_rng());
+        let (eprivkey, _) = secp.generate_keypair(&mut rand::thread_rng());
+        let card_nonce = rand_nonce(&mut rand::thread_rng());
+        let cvc = "123456";
+
+        let (epubkey, xcvc) = calc_xcvc(
+            &secp,
+            &ReadCommand::name(),
+            &card_nonce,
+            &card_pubkey,
+            &eprivkey,
+            cvc,
+        );
+        assert_eq!(xcvc.len(), cvc.len());
+
+        // card side decryption using its private key and the app's ephemeral public key
+        let session_key = SharedSecret::new(&epubkey, &card_privkey);
+        let md = sha256::Hash::hash(&[card_nonce.as_slice(), b"read"].concat());
+        let decrypted: Vec<u8> = xcvc
+            .iter()
+            .zip(session_key.as_ref())
+            .zip(md.as_byte_array())
+            .map(|((x, k), m)| x ^ k ^ m)
+            .collect();
+        assert_eq!(decrypted, cvc.as_bytes());
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_fn_transport() {
+        use crate::mock::synthetic;
+
+        let mut commands = 0;
+        let transport = FnTransport::from_fn(|command_apdu: &[u8]| {
+            commands += 1;
+            assert_eq!(command_apdu[..4], SELECT_CLA_INS_P1P2);
+            Ok(synthetic::TAPSIGNER_STATUS.to_vec())
+        });
+        let card = transport.to_cktap().unwrap();
+        assert!(matches!(card, CkTapCard::TapSigner(_)));
+        drop(card);
+        assert_eq!(commands, 1);
+
+        let transport =
+            FnTransport::from_fn(|_: &[u8]| Err(Error::Transport("no tag".to_string())));
+        assert!(matches!(transport.to_cktap(), Err(Error::Transport(_))));
+    }
+
+    #[cfg(feature = "test-utils")]
+    fn wait_response(auth_delay: u64) -> Vec<u8> {
+        use ciborium::value::Value;
+
+        cbor_map(vec![
+            ("success", Value::Bool(true)),
+            ("auth_delay", auth_delay.into()),
+        ])
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_session_keys_reused() {
+        let mut card = mock_satscard(vec![]);
+        assert!(card.session_keys().is_none());
+
+        let (eprivkey, epubkey, xcvc) = card.calc_ekeys_xcvc("123456".to_string(), "read").unwrap();
+        card.set_card_nonce(vec![0x42; CARD_NONCE_SIZE]);
+        let (eprivkey2, epubkey2, xcvc2) =
+            card.calc_ekeys_xcvc("123456".to_string(), "read").unwrap();
+        assert_eq!(eprivkey, eprivkey2);
+        assert_eq!(epubkey, epubkey2);
+        assert_ne!(xcvc, xcvc2);
+
+        let session_key = SharedSecret::new(card.pubkey(), &eprivkey).secret_bytes();
+        assert_eq!(card.session_keys().unwrap().session_key(), &session_key);
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_session_keys_replaced_after_error() {
+        use crate::mock::synthetic;
+
+        let mut card = mock_satscard(vec![
+            synthetic::BAD_AUTH_ERROR.to_vec(),
+            synthetic::BAD_AUTH_ERROR.to_vec(),
+        ]);
+        assert!(!card.test_cvc("000000".to_string()).unwrap());
+        let epubkey = card.session_keys().unwrap().epubkey;
+        // the retry uses the same card nonce, but not the same ephemeral key and xcvc
+        assert!(!card.test_cvc("000000".to_string()).unwrap());
+        assert_ne!(card.session_keys().unwrap().epubkey, epubkey);
+        let sent = card.transport.sent();
+        assert_eq!(sent.len(), 3);
+        assert_ne!(sent[1], sent[2]);
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_clear_auth_delay() {
+        let mut card = mock_satscard(vec![wait_response(1), wait_response(0)]);
+        card.set_auth_delay(Some(2));
+
+        let mut remaining = Vec::new();
+        card.clear_auth_delay(|auth_delay| remaining.push(auth_delay))
+            .unwrap();
+        assert_eq!(remaining, vec![2, 1]);
+        assert_eq!(card.auth_delay, None);
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_auth_delay_duration() {
+        let mut card = mock_satscard(vec![]);
+        assert_eq!(card.auth_delay_duration(), None);
+        card.set_auth_delay(Some(12));
+        assert_eq!(card.auth_delay_duration(), Some(Duration::from_secs(12)));
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_wait_iter() {
+        let mut card = mock_satscard(vec![wait_response(1), wait_response(0)]);
+        assert_eq!(card.wait_iter().count(), 0);
+
+        card.set_auth_delay(Some(2));
+        let remaining: Result<Vec<usize>, Error> = card.wait_iter().collect();
+        assert_eq!(remaining.unwrap(), vec![1, 0]);
+        assert_eq!(card.auth_delay, None);
+
+        // the nonce for the next command is kept
+        card.transport.push_response(cbor_map(vec![
+            ("success", ciborium::value::Value::Bool(true)),
+            ("auth_delay", 0.into()),
+            (
+                "card_nonce",
+                ciborium::value::Value::Bytes(vec![0x42; CARD_NONCE_SIZE]),
+            ),
+        ]));
+        card.set_auth_delay(Some(1));
+        assert_eq!(card.wait_iter().count(), 1);
+        assert_eq!(card.card_nonce, vec![0x42; CARD_NONCE_SIZE]);
+
+        // a failed wait ends the countdown
+        card.set_auth_delay(Some(2));
+        let mut waits = card.wait_iter();
+        assert!(matches!(waits.next(), Some(Err(_))));
+        assert!(waits.next().is_none());
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_transmit_command() {
+        use crate::mock::{synthetic, MockTransport};
+
+        let transport = MockTransport::new(vec![
+            synthetic::SATSCARD_STATUS.to_vec(),
+            synthetic::BAD_AUTH_ERROR.to_vec(),
+        ]);
+        let commands: Vec<Command> = vec![
+            StatusCommand::default().into(),
+            WaitCommand::new(None, None).into(),
+        ];
+        let mut responses = commands
+            .iter()
+            .map(|command| transport.transmit_command(command));
+        assert!(matches!(responses.next(), Some(Ok(Response::Status(_)))));
+        let error = responses.next().unwrap().unwrap_err();
+        assert_eq!(error.cktap_code(), Some(CkTapErrorCode::BadAuth));
+        assert_eq!(commands[1].name(), "wait");
+        assert_eq!(
+            transport.sent(),
+            vec![
+                StatusCommand::default().apdu_bytes().unwrap(),
+                WaitCommand::new(None, None).apdu_bytes().unwrap()
+            ]
+        );
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_build_command_bytes() {
+        let mut card = mock_satscard(vec![wait_response(0)]);
+        let apdu = card
+            .build_command_bytes("123456".to_string(), |epubkey, xcvc| {
+                Ok(WaitCommand::new(
+                    Some(epubkey.serialize().to_vec()),
+                    Some(xcvc),
+                ))
+            })
+            .unwrap();
+        // nothing is sent until the command is run
+        assert_eq!(card.transport.sent().len(), 1);
+
+        card.wait(Some("123456".to_string())).unwrap();
+        assert_eq!(card.transport.sent().last(), Some(&apdu));
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_transmit_raw() {
+        use ciborium::value::Value;
+
+        let status = satscard_status_with("card_nonce", Value::Bytes(vec![0x42; 16]));
+        let mut card = mock_satscard(vec![status.clone()]);
+        let apdu = StatusCommand::default().apdu_bytes().unwrap();
+        assert_eq!(card.transmit_raw(&apdu).unwrap(), status);
+        assert_eq!(card.transport.sent().last(), Some(&apdu));
+        assert_eq!(card.card_nonce, vec![0x42; 16]);
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_test_cvc() {
+        use crate::mock::synthetic;
+
+        let mut card = mock_satscard(vec![synthetic::BAD_AUTH_ERROR.to_vec(), wait_response(0)]);
+        assert!(!card.test_cvc("000000".to_string()).unwrap());
+        assert_eq!(card.cvc_tries_left(), 2);
+        assert!(card.test_cvc("123456".to_string()).unwrap());
+        assert_eq!(card.cvc_tries_left(), 3);
+
+        card.set_auth_delay(Some(5));
+        let result = card.test_cvc("123456".to_string());
+        assert!(matches!(
+            result,
+            Err(Error::AuthDelayPending { auth_delay: 5 })
+        ));
+        assert_eq!(card.transport.sent().len(), 3);
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_birth_height() {
+        use ciborium::value::Value;
+
+        let mut card = mock_satscard(vec![]);
+        assert_eq!(card.birth_height().unwrap(), 700_000);
+        card.birth = u32::MAX as usize + 1;
+        assert!(matches!(
+            card.birth_height(),
+            Err(Error::MalformedResponse { field: "birth" })
+        ));
+
+        let status = satscard_status_with("birth", Value::Integer((u32::MAX as u64 + 1).into()));
+        assert!(matches!(
+            StatusResponse::from_cbor(status),
+            Err(Error::MalformedResponse { field: "birth" })
+        ));
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_stale_nonce() {
+        use crate::mock::synthetic;
+
+        // the card keeps its nonce after an error response, so a retry can use it
+        let mut card = mock_satscard(vec![synthetic::BAD_AUTH_ERROR.to_vec()]);
+        assert!(!card.test_cvc("000000".to_string()).unwrap());
+
+        // no response, the card may have moved on to a new nonce
+        assert!(matches!(
+            card.wait(Some("123456".to_string())),
+            Err(Error::Mock(_))
+        ));
+        let result = card.wait(Some("123456".to_string()));
+        assert!(matches!(result, Err(Error::NonceReuse(_))));
+        let result = card.build_command_bytes("123456".to_string(), |epubkey, xcvc| {
+            Ok(WaitCommand::new(
+                Some(epubkey.serialize().to_vec()),
+                Some(xcvc),
+            ))
+        });
+        assert!(matches!(result, Err(Error::NonceReuse(_))));
+        assert_eq!(card.transport.sent().len(), 2);
+
+        // a status learns the current nonce, even if it is the same one
+        card.transport
+            .push_response(synthetic::SATSCARD_STATUS.to_vec());
+        card.transport.push_response(wait_response(0));
+        card.status().unwrap();
+        assert!(card.test_cvc("123456".to_string()).unwrap());
+
+        card.card_nonce = vec![];
+        let result = card.wait(Some("123456".to_string()));
+        assert!(matches!(result, Err(Error::InvalidNonce(_))));
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_check_cvc() {
+        assert!(check_cvc("123456").is_ok());
+        assert!(check_cvc(&"a".repeat(32)).is_ok());
+        assert!(matches!(
+            check_cvc("12345"),
+            Err(Error::InvalidCvcLength { len: 5 })
+        ));
+        assert!(matches!(check_cvc("12345\n"), Err(Error::InvalidCvc(_))));
+        assert!(matches!(check_cvc("12345é"), Err(Error::InvalidCvc(_))));
+
+        // rejected before anything is sent to the card
+        let mut card = mock_satscard(vec![]);
+        let result = card.unseal(card.active_slot(), "12345".to_string());
+        assert!(matches!(result, Err(Error::InvalidCvcLength { len: 5 })));
+        let result = card.wait(Some("12345".to_string()));
+        assert!(matches!(result, Err(Error::InvalidCvcLength { len: 5 })));
+        assert_eq!(card.transport.sent().len(), 1);
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_authenticated() {
+        let card_error = |code| Error::CkTap {
+            error: "card error".to_string(),
+            code,
+        };
+
+        let mut card = mock_satscard(vec![wait_response(0)]);
+        let result: Result<(), Error> =
+            card.authenticated("000000".to_string(), |_, _| Err(card_error(401)));
+        assert!(matches!(result, Err(Error::WrongCvc { tries_left: 2 })));
+        assert_eq!(card.cvc_tries_left(), 2);
+
+        // rate limited once, retried after the delay
+        let mut calls = 0;
+        let result = card.authenticated("123456".to_string(), |_, cvc| {
+            calls += 1;
+            if calls == 1 {
+                Err(card_error(429))
+            } else {
+                Ok(cvc)
+            }
+        });
+        assert_eq!(result.unwrap(), "123456");
+        assert_eq!(calls, 2);
+        assert_eq!(card.cvc_tries_left(), CVC_ATTEMPTS);
+    }
+
+    /// The SATSCARD status fixture with one field replaced.
+    #[cfg(feature = "test-utils")]
+    fn satscard_status_with(field: &str, new_value: ciborium::value::Value) -> Vec<u8> {
+        status_with(crate::mock::synthetic::SATSCARD_STATUS, field, new_value)
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_shared_commands() {
+        use crate::mock::{synthetic, MockTransport};
+        use ciborium::value::Value;
+
+        let transport = MockTransport::new(vec![synthetic::SATSCARD_STATUS.to_vec()]);
+        let mut card = transport.to_cktap().unwrap();
+        let status = satscard_status_with("card_nonce", Value::Bytes(vec![0x42; 16]));
+        let status = status_with(&status, "auth_delay", Value::Integer(1.into()));
+        if let CkTapCard::SatsCard(sc) = &card {
+            sc.transport.push_response(status);
+            sc.transport.push_response(wait_response(0));
+        }
+
+        let shared: &mut dyn SharedCommands = &mut card;
+        assert_eq!(shared.status().unwrap().auth_delay, Some(1));
+        assert_eq!(shared.card_nonce(), &[0x42; 16][..]);
+        assert_eq!(shared.wait(None).unwrap().auth_delay, 0);
+        assert_eq!(shared.auth_delay(), None);
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_borrowed_transport() {
+        use crate::mock::{synthetic, MockTransport};
+
+        let mut transport = MockTransport::new(vec![
+            synthetic::SATSCARD_STATUS.to_vec(),
+            synthetic::TAPSIGNER_STATUS.to_vec(),
+        ]);
+        // one session per tapped card, the transport stays with the caller
+        let card = (&mut transport).to_cktap().unwrap();
+        assert!(matches!(card, CkTapCard::SatsCard(_)));
+        let card = (&transport).to_cktap().unwrap();
+        assert!(matches!(card, CkTapCard::TapSigner(_)));
+        assert_eq!(transport.sent().len(), 2);
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_status_refresh() {
+        use crate::mock::{synthetic, MockTransport};
+        use ciborium::value::Value;
+
+        let mut card = MockTransport::new(vec![synthetic::SATSCARD_STATUS.to_vec()])
+            .to_cktap()
+            .unwrap();
+        if let CkTapCard::SatsCard(sc) = &mut card {
+            let status = status_with(
+                &satscard_status_with("slots", Value::Array(vec![1.into(), 10.into()])),
+                "addr",
+                Value::Null,
+            );
+            sc.transport.push_response(status);
+        }
+        let status = card.status().unwrap();
+        assert_eq!(status.slots, Some((1, 10)));
+        let sc = expect_satscard(card);
+        assert_eq!(sc.slots, (1, 10));
+        assert_eq!(sc.addr, None);
+
+        let mut card = MockTransport::new(vec![synthetic::TAPSIGNER_STATUS.to_vec()])
+            .to_cktap()
+            .unwrap();
+        if let Some(ts) = card.tap_signer_mut() {
+            ts.transport.push_response(status_with(
+                synthetic::TAPSIGNER_STATUS,
+                "num_backups",
+                2.into(),
+            ));
+            ts.status().unwrap();
+            assert_eq!(ts.num_backups, Some(2));
+        }
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_card_classification() {
+        use crate::mock::{synthetic, MockTransport};
+        use ciborium::value::Value;
+
+        let card_type = |status: &[u8]| StatusResponse::from_cbor(status.to_vec())?.card_type();
+
+        let status = status_with(synthetic::TAPSIGNER_STATUS, "satschip", Value::Bool(true));
+        assert_eq!(card_type(&status).unwrap(), CardKind::SatsChip);
+        let card = MockTransport::new(vec![status]).to_cktap().unwrap();
+        assert!(matches!(card, CkTapCard::SatsChip(_)));
+        assert!(card.tap_signer().is_some());
+
+        let status = status_with(synthetic::TAPSIGNER_STATUS, "satschip", Value::Bool(false));
+        assert_eq!(card_type(&status).unwrap(), CardKind::TapSigner);
+        let card = MockTransport::new(vec![status]).to_cktap().unwrap();
+        assert!(matches!(card, CkTapCard::TapSigner(_)));
+
+        assert_eq!(
+            card_type(synthetic::SATSCARD_STATUS).unwrap(),
+            CardKind::SatsCard
+        );
+
+        // neither a TAPSIGNER nor a SATSCARD with slots
+        let status = satscard_status_with("slots", Value::Null);
+        assert!(matches!(card_type(&status), Err(Error::UnknownCardType(_))));
+        let result = MockTransport::new(vec![status]).to_cktap();
+        assert!(matches!(result, Err(Error::UnknownCardType(_))));
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_verify_card_pubkey_mismatch() {
+        use ciborium::value::Value;
+
+        let secp = Secp256k1::new();
+        let (_, other) = secp.generate_keypair(&mut rand::thread_rng());
+        let status = satscard_status_with("pubkey", Value::Bytes(other.serialize().to_vec()));
+
+        let card = mock_satscard(vec![status]);
+        assert!(matches!(
+            card.verify_card(),
+            Err(Error::InvalidPublicKey(_))
+        ));
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_verify_card_with_roots() {
+        use crate::mock::synthetic;
+        use ciborium::value::Value;
+
+        let secp = Secp256k1::new();
+        // the fixture card pubkey is the generator point
+        let mut one = [0u8; 32];
+        one[31] = 1;
+        let card_priv = SecretKey::from_slice(&one).unwrap();
+        let (batch_priv, batch_key) = secp.generate_keypair(&mut rand::thread_rng());
+        let (root_priv, root_key) = secp.generate_keypair(&mut rand::thread_rng());
+        let cert = |signer: &SecretKey, signed: &PublicKey| {
+            let md = Message::from_hashed_data::<sha256::Hash>(&signed.serialize());
+            let (rec_id, sig) = secp.sign_ecdsa_recoverable(&md, signer).serialize_compact();
+            let mut cert = vec![39 + rec_id.to_i32() as u8];
+            cert.extend(sig);
+            Value::Bytes(cert)
+        };
+        let cert_chain = Value::Array(vec![
+            cert(&batch_priv, &PublicKey::from_secret_key(&secp, &card_priv)),
+            cert(&root_priv, &batch_key),
+        ]);
+        let sats_chip_status =
+            status_with(synthetic::TAPSIGNER_STATUS, "satschip", Value::Bool(true));
+
+        let transport = |status: Vec<u8>| {
+            let cert_chain = cert_chain.clone();
+            fake_card(&status, move |command, card_nonce| match command.name() {
+                Some("certs") => vec![("cert_chain", cert_chain.clone())],
+                Some("check") => {
+                    let mut message = b"OPENDIME".to_vec();
+                    message.extend(card_nonce);
+                    message.extend(command.bytes("nonce").unwrap());
+                    let digest = Message::from_hashed_data::<sha256::Hash>(&message);
+                    let sig = Secp256k1::new().sign_ecdsa(&digest, &card_priv);
+                    vec![("auth_sig", Value::Bytes(sig.serialize_compact().to_vec()))]
+                }
+                cmd => panic!("unexpected command {:?}", cmd),
+            })
+        };
+        let tap_signer = || {
+            expect_tapsigner(
+                transport(synthetic::TAPSIGNER_STATUS.to_vec())
+                    .to_cktap()
+                    .unwrap(),
+            )
+        };
+
+        let verified = tap_signer().verify_card_with_roots(&[root_key]).unwrap();
+        assert_eq!(verified.root_key(), &root_key);
+        // not signed by the production root
+        assert!(matches!(
+            tap_signer().verify_card(),
+            Err(Error::IncorrectSignature(_))
+        ));
+        assert!(matches!(
+            tap_signer().check_certificate(),
+            Err(Error::IncorrectSignature(_))
+        ));
+
+        // a third party can check a SATSCHIP without its CVC
+        let pubkey = transport(sats_chip_status.clone())
+            .verify_genuine_with_roots(&[root_key])
+            .unwrap();
+        assert_eq!(pubkey, PublicKey::from_secret_key(&secp, &card_priv));
+        assert!(matches!(
+            transport(sats_chip_status).verify_genuine(),
+            Err(Error::IncorrectSignature(_))
+        ));
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_unsupported_proto() {
+        use crate::mock::MockTransport;
+
+        let status = satscard_status_with("proto", (SUPPORTED_PROTO as u64 + 1).into());
+        let transport = MockTransport::new(vec![status.clone()]);
+        assert!(matches!(
+            transport.to_cktap(),
+            Err(Error::UnsupportedProtocol {
+                got: 2,
+                supported: 1
+            })
+        ));
+
+        let transport = MockTransport::new(vec![status]);
+        let sc = expect_satscard(transport.to_cktap_any_proto().unwrap());
+        assert_eq!(sc.proto, SUPPORTED_PROTO + 1);
+    }
+
+    #[cfg(feature = "emulator")]
     #[test]
     fn test_new_command() {
         let rng = &mut rand::thread_rng();
@@ -232,30 +1469,43 @@ mod tests {
         match emulator {
             CkTapCard::SatsCard(mut sc) => {
                 let current_slot = sc.slots.0;
-                let response = sc.unseal(current_slot, CVC.to_string());
+                let response = sc.unseal(sc.active_slot(), CVC.to_string());
                 assert!(response.is_ok());
-                let response = sc.new_slot(current_slot + 1, Some(chain_code), CVC.to_string());
+                let next_slot = sc.next_slot().unwrap();
+                let response = sc.new_slot(next_slot, Some(chain_code), CVC.to_string());
                 assert!(response.is_ok());
                 assert_eq!(sc.slots.0, current_slot + 1);
                 // test with no new chain_code
                 let current_slot = sc.slots.0;
-                let response = sc.unseal(current_slot, CVC.to_string());
+                let response = sc.unseal(sc.active_slot(), CVC.to_string());
                 assert!(response.is_ok());
-                let response = sc.new_slot(current_slot + 1, None, CVC.to_string());
+                let next_slot = sc.next_slot().unwrap();
+                let response = sc.new_slot(next_slot, None, CVC.to_string());
                 assert!(response.is_ok());
                 assert_eq!(sc.slots.0, current_slot + 1);
             }
-            CkTapCard::TapSigner(mut ts) => {
+            CkTapCard::TapSigner(mut ts) | CkTapCard::SatsChip(mut ts) => {
+                let initialized = ts.path.is_some();
                 let response = ts.init(chain_code, CVC.to_string());
-                assert!(response.is_ok())
-            }
-            CkTapCard::SatsChip(mut sc) => {
-                let response = sc.init(chain_code, CVC.to_string());
-                assert!(response.is_ok())
+                if initialized {
+                    assert!(matches!(response, Err(Error::AlreadyInitialized)));
+                } else {
+                    assert!(response.is_ok())
+                }
             }
         };
     }
 
+    #[cfg(feature = "emulator")]
+    #[test]
+    fn test_sign_invalid_digest() {
+        let emulator = find_emulator().unwrap();
+        if let CkTapCard::TapSigner(mut ts) | CkTapCard::SatsChip(mut ts) = emulator {
+            let response = ts.sign(vec![0u8; 31], None, CVC.to_string());
+            assert!(matches!(response, Err(Error::InvalidDigest(_))));
+        }
+    }
+
     // #[test]
     // fn test_tapsigner_signature() {
     //     let card_pubkey = PublicKey::from_slice(
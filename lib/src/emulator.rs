@@ -2,6 +2,7 @@ use crate::apdu::{AppletSelect, CommandApdu, Error, StatusCommand};
 use crate::commands::CkTransport;
 use crate::CkTapCard;
 use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
 use std::os::unix::net::UnixStream;
 use std::path::Path;
 use std::string::ToString;
@@ -14,43 +15,152 @@ pub fn find_emulator() -> Result<CkTapCard<CardEmulator>, Error> {
         return Err(Error::Emulator("Emulator pipe doesn't exist.".to_string()));
     }
     let stream = UnixStream::connect("/tmp/ecard-pipe").expect("unix stream");
-    let card_emulator = CardEmulator { stream };
+    let card_emulator = CardEmulator {
+        stream: EmulatorStream::Unix(stream),
+    };
+    card_emulator.to_cktap()
+}
+
+/// Connect to an emulator socket forwarded to a TCP address.
+pub fn find_emulator_tcp<A: ToSocketAddrs>(addr: A) -> Result<CkTapCard<CardEmulator>, Error> {
+    let stream = TcpStream::connect(addr).map_err(|e| Error::Emulator(e.to_string()))?;
+    let card_emulator = CardEmulator {
+        stream: EmulatorStream::Tcp(stream),
+    };
     card_emulator.to_cktap()
 }
 
+#[derive(Debug)]
+enum EmulatorStream {
+    Unix(UnixStream),
+    Tcp(TcpStream),
+}
+
+impl EmulatorStream {
+    fn exchange(&self, message: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            EmulatorStream::Unix(stream) => exchange(&mut &*stream, message),
+            EmulatorStream::Tcp(stream) => exchange(&mut &*stream, message),
+        }
+    }
+}
+
+/// Write `message` and read the response, which may arrive over several reads, until it decodes
+/// as one CBOR value or the emulator closes the stream.
+fn exchange<S: Read + Write>(stream: &mut S, message: &[u8]) -> std::io::Result<Vec<u8>> {
+    stream.write_all(message)?;
+    let mut response = Vec::new();
+    let mut buffer = [0; 4096];
+    loop {
+        let len = stream.read(&mut buffer)?;
+        response.extend_from_slice(&buffer[..len]);
+        let complete = !matches!(
+            ciborium::de::from_reader::<ciborium::value::Value, _>(response.as_slice()),
+            Err(ciborium::de::Error::Io(_))
+        );
+        if len == 0 || complete {
+            return Ok(response);
+        }
+    }
+}
+
+/// Transport for the `ecard.py` emulator from the coinkite-tap-proto repo, which speaks cktap
+/// protocol version 1. The emulator listens on the `/tmp/ecard-pipe` Unix socket and exchanges bare
+/// CBOR messages, the APDU header is stripped from commands and responses carry no status word.
+///
+/// The socket can be forwarded over TCP to reach an emulator in another container or host, e.g.
+/// `socat TCP-LISTEN:7771,reuseaddr,fork UNIX-CONNECT:/tmp/ecard-pipe`.
 #[derive(Debug)]
 pub struct CardEmulator {
-    stream: UnixStream,
+    stream: EmulatorStream,
 }
 
 impl CkTransport for CardEmulator {
     fn transmit_apdu(&self, command_apdu: Vec<u8>) -> Result<Vec<u8>, Error> {
         // convert select_apdu into StatusCommand apdu bytes
-        let select_apdu: Vec<u8> = AppletSelect::default().apdu_bytes();
+        let select_apdu: Vec<u8> = AppletSelect::default().apdu_bytes()?;
         let command_apdu = if command_apdu.eq(&select_apdu) {
-            StatusCommand::default().apdu_bytes()
+            StatusCommand::default().apdu_bytes()?
         } else {
             command_apdu
         };
 
-        let mut stream = self.stream.try_clone().expect("clone unix stream");
-
         // trim first 5 bytes from command apdu bytes to get the cbor data
-        stream
-            .write_all(&command_apdu.as_slice()[5..])
-            .map_err(|e| Error::Emulator(e.to_string()))?;
-        let mut buffer = [0; 4096];
-        // read up to 4096 bytes
-        stream
-            .read(&mut buffer[..])
-            .map_err(|e| Error::Emulator(e.to_string()))?;
-        Ok(buffer.to_vec())
+        self.stream
+            .exchange(&command_apdu.as_slice()[5..])
+            .map_err(|e| Error::Emulator(e.to_string()))
     }
 }
 
 #[cfg(test)]
 pub mod test {
-    use crate::emulator::find_emulator;
+    use crate::emulator::{exchange, find_emulator, find_emulator_tcp};
+    use std::io::{Read, Write};
+
+    const TAPSIGNER_STATUS: &[u8] = include_bytes!("../tests/synthetic/tapsigner_status.cbor");
+
+    #[test]
+    pub fn test_transmit_tcp() {
+        use crate::apdu::{CommandApdu, StatusCommand};
+        use crate::CkTapCard;
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let emulator = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buffer = [0; 4096];
+            let len = stream.read(&mut buffer).unwrap();
+            // applet select is sent to the emulator as a bare status command
+            assert_eq!(
+                buffer[..len],
+                StatusCommand::default().apdu_bytes().unwrap()[5..]
+            );
+            // the response is split across writes and the stream is left open
+            let (head, tail) = TAPSIGNER_STATUS.split_at(TAPSIGNER_STATUS.len() / 2);
+            stream.write_all(head).unwrap();
+            stream.flush().unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            stream.write_all(tail).unwrap();
+            stream
+        });
+
+        let card = find_emulator_tcp(addr).unwrap();
+        assert!(matches!(card, CkTapCard::TapSigner(_)));
+        emulator.join().unwrap();
+    }
+
+    #[test]
+    pub fn test_exchange_returns_only_the_response() {
+        let mut pipe = Pipe {
+            written: Vec::new(),
+            response: std::io::Cursor::new(TAPSIGNER_STATUS.to_vec()),
+        };
+        assert_eq!(exchange(&mut pipe, b"ping").unwrap(), TAPSIGNER_STATUS);
+        assert_eq!(pipe.written, b"ping");
+    }
+
+    /// Stream that records writes and answers reads from `response`.
+    struct Pipe {
+        written: Vec<u8>,
+        response: std::io::Cursor<Vec<u8>>,
+    }
+
+    impl Read for Pipe {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.response.read(buf)
+        }
+    }
+
+    impl Write for Pipe {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.written.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
 
     #[test]
     pub fn test_transmit() {
@@ -1,9 +1,11 @@
 use crate::apdu::Error;
-use secp256k1::hashes::hex::ToHex;
+use alloc::string::{String, ToString};
+use core::convert::TryFrom;
+use core::fmt;
+use core::fmt::Debug;
+use core::str::FromStr;
+use hex::DisplayHex;
 use secp256k1::PublicKey;
-use std::convert::TryFrom;
-use std::fmt;
-use std::fmt::Debug;
 
 /// Published Coinkite factory root keys.
 const PUB_FACTORY_ROOT_KEY: &str =
@@ -12,6 +14,16 @@ const PUB_FACTORY_ROOT_KEY: &str =
 const DEV_FACTORY_ROOT_KEY: &str =
     "027722ef208e681bac05f1b4b3cc478d6bf353ac9a09ff0c843430138f65c27bab";
 
+/// The published Coinkite factory root key, the only root `Certificate::verify_card` trusts.
+pub fn factory_root_pubkey() -> PublicKey {
+    PublicKey::from_str(PUB_FACTORY_ROOT_KEY).expect("valid public key")
+}
+
+/// The dev root key that signs emulator and test cards, never trust it for real funds.
+pub fn dev_factory_root_pubkey() -> PublicKey {
+    PublicKey::from_str(DEV_FACTORY_ROOT_KEY).expect("valid public key")
+}
+
 pub enum FactoryRootKey {
     Pub(PublicKey),
     Dev(PublicKey),
@@ -21,7 +33,7 @@ impl TryFrom<PublicKey> for FactoryRootKey {
     type Error = Error;
 
     fn try_from(pubkey: PublicKey) -> Result<Self, Error> {
-        match pubkey.serialize().to_hex().as_str() {
+        match pubkey.serialize().to_lower_hex_string().as_str() {
             PUB_FACTORY_ROOT_KEY => Ok(FactoryRootKey::Pub(pubkey)),
             DEV_FACTORY_ROOT_KEY => Ok(FactoryRootKey::Dev(pubkey)),
             _ => Err(Error::IncorrectSignature(
@@ -31,7 +43,22 @@ impl TryFrom<PublicKey> for FactoryRootKey {
     }
 }
 
+/// Every known factory root key, production first. Cards are certified through a per batch key
+/// that is not published, read it from the card with `Certificate::cert_chain`.
+pub fn factory_root_keys() -> [FactoryRootKey; 2] {
+    [
+        FactoryRootKey::Pub(factory_root_pubkey()),
+        FactoryRootKey::Dev(dev_factory_root_pubkey()),
+    ]
+}
+
 impl FactoryRootKey {
+    pub fn pubkey(&self) -> &PublicKey {
+        match &self {
+            FactoryRootKey::Pub(pk) | FactoryRootKey::Dev(pk) => pk,
+        }
+    }
+
     pub fn name(&self) -> String {
         match &self {
             FactoryRootKey::Pub(_) => "Root Factory Certificate".to_string(),
@@ -52,3 +79,21 @@ impl Debug for FactoryRootKey {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_factory_root_keys() {
+        let [production, dev] = factory_root_keys();
+        assert_eq!(
+            production.pubkey().serialize().to_lower_hex_string(),
+            PUB_FACTORY_ROOT_KEY
+        );
+        assert!(matches!(
+            FactoryRootKey::try_from(*dev.pubkey()),
+            Ok(FactoryRootKey::Dev(_))
+        ));
+    }
+}
@@ -1,34 +1,112 @@
-extern crate core;
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+#[cfg(feature = "std")]
+pub extern crate bitcoin;
 pub extern crate secp256k1;
 
-use secp256k1::ecdsa::Signature;
-use secp256k1::hashes::sha256;
-use secp256k1::rand;
-use secp256k1::rand::rngs::ThreadRng;
+#[cfg(feature = "std")]
+use aes::cipher::{KeyIvInit, StreamCipher};
+#[cfg(feature = "std")]
+use bitcoin::{
+    bip32::{ChildNumber, DerivationPath, Fingerprint, Xpriv, Xpub},
+    ecdsa,
+    hashes::Hash,
+    hex::FromHex,
+    psbt::Psbt,
+    sighash::SighashCache,
+    sign_message::{signed_msg_hash, MessageSignature},
+    Address, Network, PrivateKey,
+};
+#[cfg(feature = "std")]
+use core::{fmt, fmt::Debug, str::FromStr};
 use secp256k1::rand::Rng;
-use secp256k1::{All, Message, PublicKey, Secp256k1};
-// use core::slice::SlicePattern;
-use std::fmt;
-use std::fmt::Debug;
+#[cfg(feature = "std")]
+use secp256k1::rand::RngCore;
+#[cfg(feature = "std")]
+use secp256k1::{
+    ecdh::SharedSecret,
+    ecdsa::{RecoverableSignature, RecoveryId, Signature},
+    hashes::sha256,
+    All, Message, PublicKey, Secp256k1, SecretKey,
+};
+#[cfg(feature = "std")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "std")]
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+#[cfg(feature = "std")]
+use zeroize::Zeroizing;
+
+#[cfg(all(feature = "pcsc", not(feature = "std")))]
+compile_error!("the `pcsc` feature requires the `std` feature");
+#[cfg(all(feature = "tokio", not(feature = "std")))]
+compile_error!("the `tokio` feature requires the `std` feature");
+
+/// Hardened BIP-32 derivation path component bit.
+pub const HARDENED: u32 = 1 << 31;
+
+/// The hardened path component for `index`, eg. `harden(84)` for `84'`. An index that already has
+/// the `HARDENED` bit set would be ambiguous, so it is rejected with `Error::InvalidPath` instead of
+/// silently passed through.
+pub fn harden(index: u32) -> Result<u32, Error> {
+    if index & HARDENED != 0 {
+        return Err(Error::InvalidPath(alloc::format!(
+            "Index {} is too large to harden, it must be below {}",
+            index,
+            HARDENED
+        )));
+    }
+    Ok(index | HARDENED)
+}
+
+/// Number of times a sign command is sent while the card responds with error 205 (unlucky number).
+#[cfg(feature = "std")]
+const SIGN_ATTEMPTS: usize = 5;
+
+#[cfg(feature = "std")]
+type Aes128Ctr = ctr::Ctr128BE<aes::Aes128>;
+
+/// Accepted TAPSIGNER CVC length range in bytes.
+#[cfg(feature = "std")]
+pub(crate) const CVC_LENGTH: core::ops::RangeInclusive<usize> = 6..=32;
 
 pub mod apdu;
 pub mod commands;
 pub mod factory_root_key;
 
+#[cfg(feature = "tokio")]
+pub mod async_transport;
 #[cfg(feature = "emulator")]
 pub mod emulator;
+#[cfg(feature = "test-utils")]
+pub mod mock;
 #[cfg(feature = "pcsc")]
 pub mod pcsc;
+#[cfg(feature = "qr")]
+pub mod qr;
+#[cfg(feature = "recording")]
+pub mod recording;
+#[cfg(feature = "web-nfc")]
+pub mod web_nfc;
 
 use apdu::*;
+#[cfg(feature = "std")]
 use commands::*;
 
+/// Cards are `Clone` when their transport is, so callers can keep a copy of the card state (nonce,
+/// path, auth delay) from before a command to retry from.
+#[cfg(feature = "std")]
+#[derive(Clone)]
 pub enum CkTapCard<T: CkTransport> {
     SatsCard(SatsCard<T>),
     TapSigner(TapSigner<T>),
+    /// TAPSIGNER in a chip form factor for embedding in other devices. Its status sets both the
+    /// `tapsigner` and `satschip` flags and it takes the TAPSIGNER commands, so it is driven with
+    /// the same `TapSigner` type, see `CkTapCard::tap_signer_mut`.
     SatsChip(TapSigner<T>),
 }
 
+#[cfg(feature = "std")]
 impl<T: CkTransport> fmt::Debug for CkTapCard<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match &self {
@@ -45,6 +123,221 @@ impl<T: CkTransport> fmt::Debug for CkTapCard<T> {
     }
 }
 
+/// One line summary of the card's non-secret identity, eg.
+/// `TAPSIGNER v1.0.3, mainnet, path m/84'/0'/0', 2 backups`.
+#[cfg(feature = "std")]
+impl<T: CkTransport> fmt::Display for CkTapCard<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (name, ver) = match self {
+            CkTapCard::SatsCard(s) => ("SATSCARD", &s.ver),
+            CkTapCard::TapSigner(t) => ("TAPSIGNER", &t.ver),
+            CkTapCard::SatsChip(t) => ("SATSCHIP", &t.ver),
+        };
+        let network = match self.network() {
+            Network::Bitcoin => "mainnet".to_string(),
+            network => network.to_string(),
+        };
+        write!(f, "{} v{}, {}", name, ver, network)?;
+        match self {
+            CkTapCard::SatsCard(s) => write!(f, ", slot {} of {}", s.slots.0, s.slots.1),
+            CkTapCard::TapSigner(t) | CkTapCard::SatsChip(t) => {
+                match t.derivation_path().ok().flatten() {
+                    Some(path) => write!(f, ", path {}", path)?,
+                    None => write!(f, ", not set up")?,
+                }
+                match t.num_backups {
+                    Some(1) => write!(f, ", 1 backup"),
+                    Some(n) => write!(f, ", {} backups", n),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T: CkTransport> CkTapCard<T> {
+    /// Select the applet, read the status it returns and pick the card variant from its
+    /// `tapsigner`, `satschip` and `slots` fields. For custom transports, same as
+    /// `CkTransport::to_cktap`.
+    pub fn from_transport(transport: T) -> Result<Self, Error> {
+        transport.to_cktap()
+    }
+
+    /// The TAPSIGNER or SATSCHIP, `None` for a SATSCARD.
+    pub fn tap_signer(&self) -> Option<&TapSigner<T>> {
+        match self {
+            CkTapCard::TapSigner(ts) | CkTapCard::SatsChip(ts) => Some(ts),
+            CkTapCard::SatsCard(_) => None,
+        }
+    }
+
+    /// The TAPSIGNER or SATSCHIP, `None` for a SATSCARD.
+    pub fn tap_signer_mut(&mut self) -> Option<&mut TapSigner<T>> {
+        match self {
+            CkTapCard::TapSigner(ts) | CkTapCard::SatsChip(ts) => Some(ts),
+            CkTapCard::SatsCard(_) => None,
+        }
+    }
+
+    /// Render addresses, xpubs and descriptors for `network`, see `SatsCard::with_network`.
+    pub fn with_network(self, network: Network) -> Self {
+        match self {
+            CkTapCard::SatsCard(s) => CkTapCard::SatsCard(s.with_network(network)),
+            CkTapCard::TapSigner(t) => CkTapCard::TapSigner(t.with_network(network)),
+            CkTapCard::SatsChip(t) => CkTapCard::SatsChip(t.with_network(network)),
+        }
+    }
+
+    /// Pick app nonces and ephemeral keys with `rng`, see `CardRng`.
+    pub fn with_rng<R: RngCore + Send + 'static>(self, rng: R) -> Self {
+        match self {
+            CkTapCard::SatsCard(s) => CkTapCard::SatsCard(s.with_rng(rng)),
+            CkTapCard::TapSigner(t) => CkTapCard::TapSigner(t.with_rng(rng)),
+            CkTapCard::SatsChip(t) => CkTapCard::SatsChip(t.with_rng(rng)),
+        }
+    }
+
+    /// The network the card is for.
+    pub fn network(&self) -> Network {
+        match self {
+            CkTapCard::SatsCard(s) => s.network,
+            CkTapCard::TapSigner(t) | CkTapCard::SatsChip(t) => t.network,
+        }
+    }
+
+    /// Non-secret card state to cache between runs.
+    pub fn snapshot(&self) -> CardSnapshot {
+        CardSnapshot::from(self)
+    }
+
+    /// Stable identity of the card, to recognize it when it is tapped again.
+    pub fn id(&self) -> CardId {
+        match self {
+            CkTapCard::SatsCard(s) => CardId(s.pubkey),
+            CkTapCard::TapSigner(t) | CkTapCard::SatsChip(t) => CardId(t.pubkey),
+        }
+    }
+
+    /// Which commands make sense for the card now, see `SatsCard::lifecycle` and
+    /// `TapSigner::lifecycle`.
+    pub fn lifecycle(&self) -> CardLifecycle {
+        match self {
+            CkTapCard::SatsCard(s) => s.lifecycle(),
+            CkTapCard::TapSigner(t) | CkTapCard::SatsChip(t) => t.lifecycle(),
+        }
+    }
+}
+
+/// Card state from its status `path`, `slots` and `addr` fields.
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CardLifecycle {
+    /// TAPSIGNER or SATSCHIP without a key yet, `init` sets it up.
+    Uninitialized,
+    /// TAPSIGNER set up for signing, or SATSCARD with a sealed active slot to receive on.
+    Active,
+    /// SATSCARD active slot was unsealed, `new_slot` sets up the next one.
+    Unsealed,
+    /// SATSCARD last slot was unsealed, no further slots can be set up.
+    Used,
+}
+
+/// Card identity from its pubkey, which is fixed at the factory. Unlike the card nonce or slot
+/// state it doesn't change between taps, so it can key a map of known cards.
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CardId(PublicKey);
+
+#[cfg(feature = "std")]
+impl CardId {
+    pub fn pubkey(&self) -> &PublicKey {
+        &self.0
+    }
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for CardId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Kind of card, from `StatusResponse::card_type` or the card a `CardSnapshot` was taken from.
+#[cfg(feature = "std")]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CardKind {
+    SatsCard,
+    TapSigner,
+    SatsChip,
+}
+
+/// Serializable non-secret card state, to show a previously seen card without tapping it again.
+/// Nonces, auth state and session keys are not included.
+#[cfg(feature = "std")]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CardSnapshot {
+    pub kind: CardKind,
+    pub proto: usize,
+    pub ver: String,
+    pub birth: usize,
+    pub pubkey: PublicKey,
+    pub network: Network,
+    /// TAPSIGNER derivation path, `None` for a SATSCARD or if not yet set up
+    pub path: Option<Vec<usize>>,
+    pub num_backups: Option<usize>,
+    /// SATSCARD active slot and number of slots
+    pub slots: Option<(u8, u8)>,
+    /// SATSCARD partial address of the active slot
+    pub addr: Option<String>,
+}
+
+#[cfg(feature = "std")]
+impl CardSnapshot {
+    /// The TAPSIGNER derivation path, `None` for a SATSCARD or if not yet set up.
+    pub fn derivation_path(&self) -> Result<Option<DerivationPath>, Error> {
+        self.path.as_deref().map(apdu::derivation_path).transpose()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T: CkTransport> From<&CkTapCard<T>> for CardSnapshot {
+    fn from(card: &CkTapCard<T>) -> Self {
+        match card {
+            CkTapCard::SatsCard(sc) => CardSnapshot {
+                kind: CardKind::SatsCard,
+                proto: sc.proto,
+                ver: sc.ver.clone(),
+                birth: sc.birth,
+                pubkey: sc.pubkey,
+                network: sc.network,
+                path: None,
+                num_backups: None,
+                slots: Some(sc.slots),
+                addr: sc.addr.clone(),
+            },
+            CkTapCard::TapSigner(ts) | CkTapCard::SatsChip(ts) => CardSnapshot {
+                kind: if matches!(card, CkTapCard::SatsChip(_)) {
+                    CardKind::SatsChip
+                } else {
+                    CardKind::TapSigner
+                },
+                proto: ts.proto,
+                ver: ts.ver.clone(),
+                birth: ts.birth,
+                pubkey: ts.pubkey,
+                network: ts.network,
+                path: ts.path.clone(),
+                num_backups: ts.num_backups,
+                slots: None,
+                addr: None,
+            },
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+#[derive(Clone)]
 pub struct TapSigner<T: CkTransport> {
     pub transport: T,
     pub secp: Secp256k1<All>,
@@ -57,8 +350,16 @@ pub struct TapSigner<T: CkTransport> {
     pub pubkey: PublicKey,
     pub card_nonce: Vec<u8>, // 16 bytes
     pub auth_delay: Option<usize>,
+    pub bad_cvc_count: usize,
+    pub network: Network,
+    network_override: Option<Network>,
+    master_fingerprint: Option<Fingerprint>,
+    account_xpub: Option<Xpub>,
+    session_keys: Option<SessionKeys>,
+    rng: CardRng,
 }
 
+#[cfg(feature = "std")]
 impl<T: CkTransport> Authentication<T> for TapSigner<T> {
     fn secp(&self) -> &Secp256k1<All> {
         &self.secp
@@ -84,18 +385,37 @@ impl<T: CkTransport> Authentication<T> for TapSigner<T> {
         self.auth_delay = auth_delay;
     }
 
+    fn bad_cvc_count(&self) -> usize {
+        self.bad_cvc_count
+    }
+
+    fn set_bad_cvc_count(&mut self, bad_cvc_count: usize) {
+        self.bad_cvc_count = bad_cvc_count;
+    }
+
+    fn session_keys(&self) -> Option<&SessionKeys> {
+        self.session_keys.as_ref()
+    }
+
+    fn set_session_keys(&mut self, session_keys: SessionKeys) {
+        self.session_keys = Some(session_keys);
+    }
+
     fn transport(&self) -> &T {
         &self.transport
     }
+
+    fn rng(&self) -> &CardRng {
+        &self.rng
+    }
 }
 
+#[cfg(feature = "std")]
 impl<T: CkTransport> TapSigner<T> {
-    pub fn from_status(transport: T, status_response: StatusResponse) -> Self {
-        let pubkey = status_response.pubkey.as_slice(); // TODO verify is 33 bytes?
-        let pubkey = PublicKey::from_slice(pubkey)
-            .map_err(|e| Error::CiborValue(e.to_string()))
-            .unwrap();
-        Self {
+    pub fn from_status(transport: T, status_response: StatusResponse) -> Result<Self, Error> {
+        let pubkey = status_response.pubkey()?;
+        let network = status_response.network();
+        Ok(Self {
             transport,
             secp: Secp256k1::new(),
             proto: status_response.proto,
@@ -106,27 +426,114 @@ impl<T: CkTransport> TapSigner<T> {
             pubkey,
             card_nonce: status_response.card_nonce,
             auth_delay: status_response.auth_delay,
+            bad_cvc_count: 0,
+            network,
+            network_override: None,
+            master_fingerprint: None,
+            account_xpub: None,
+            session_keys: None,
+            rng: CardRng::default(),
+        })
+    }
+
+    /// Render addresses, xpubs and descriptors for `network` instead of the card's own `testnet`
+    /// flag. This is client side only, nothing changes on the card and signing is unaffected.
+    pub fn with_network(mut self, network: Network) -> Self {
+        self.network_override = Some(network);
+        self
+    }
+
+    /// The network used to render addresses, xpubs and descriptors, see `with_network`.
+    pub fn render_network(&self) -> Network {
+        self.network_override.unwrap_or(self.network)
+    }
+
+    /// Pick app nonces and the session's ephemeral key with `rng` instead of `thread_rng`, see
+    /// `CardRng`.
+    pub fn with_rng<R: RngCore + Send + 'static>(mut self, rng: R) -> Self {
+        self.rng = CardRng::new(rng);
+        self
+    }
+
+    /// The current derivation path, `None` if the card is not yet set up.
+    pub fn derivation_path(&self) -> Result<Option<DerivationPath>, Error> {
+        self.path.as_deref().map(apdu::derivation_path).transpose()
+    }
+
+    /// `Uninitialized` until `init` sets the derivation path, `Active` after.
+    pub fn lifecycle(&self) -> CardLifecycle {
+        match self.path {
+            None => CardLifecycle::Uninitialized,
+            Some(_) => CardLifecycle::Active,
         }
     }
 
-    pub fn init(&mut self, chain_code: Vec<u8>, cvc: String) -> Result<NewResponse, Error> {
-        let (_, epubkey, xcvc) = self.calc_ekeys_xcvc(cvc, &NewCommand::name());
-        let epubkey = epubkey.serialize().to_vec();
-        let new_command = NewCommand::new(Some(0), Some(chain_code), epubkey, xcvc);
-        let new_response: Result<NewResponse, Error> = self.transport.transmit(new_command);
-        if let Ok(response) = &new_response {
-            self.card_nonce = response.card_nonce.clone();
+    /// Send the status command again, eg. after a wait loop. The card nonce, auth delay, `path`,
+    /// `num_backups` and `network` are updated from it.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub fn status(&mut self) -> Result<StatusResponse, Error> {
+        let status_response = refresh_status(self)?;
+        if status_response.path != self.path {
+            self.account_xpub = None;
         }
-        new_response
+        self.path = status_response.path.clone();
+        self.num_backups = status_response.num_backups;
+        self.network = status_response.network();
+        Ok(status_response)
+    }
+
+    /// Number of backups made so far, `None` if the card is not yet set up.
+    pub fn backups_taken(&self) -> Option<usize> {
+        self.num_backups
     }
 
+    /// Block height at the card's production, see `approx_block_time`. Fails with
+    /// `Error::MalformedResponse` if `birth` is too large to be a block height.
+    pub fn birth_height(&self) -> Result<u32, Error> {
+        u32::try_from(self.birth).map_err(|_| Error::MalformedResponse { field: "birth" })
+    }
+
+    /// Set up the card's master key from `chain_code`, this can only be done once per card. The
+    /// chain code is checked with `check_chain_code` before anything is sent.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub fn init(&mut self, chain_code: Vec<u8>, cvc: String) -> Result<InitResult, Error> {
+        if self.path.is_some() {
+            return Err(Error::AlreadyInitialized);
+        }
+        check_chain_code(&chain_code)?;
+        let (_, epubkey, xcvc) = self.calc_ekeys_xcvc(cvc, &NewCommand::name())?;
+        let epubkey = epubkey.serialize().to_vec();
+        let new_command = NewCommand::new(Some(0), Some(chain_code), epubkey, xcvc);
+        let new_response: NewResponse = transmit_auth(self, new_command)?;
+        self.update_card_nonce(new_response.card_nonce)?;
+
+        // the card picks the default path, learn it from the status. The `new` already happened
+        // so the status nonce is taken as is, like any other status.
+        let status = refresh_status(self)?;
+        self.path = status.path.clone();
+        self.num_backups = status.num_backups;
+        let path = self
+            .derivation_path()?
+            .ok_or_else(|| Error::InvalidPath("Card reports no path after init".to_string()))?;
+        Ok(InitResult {
+            pubkey: status.pubkey()?,
+            path,
+            card_nonce: self.card_nonce.clone(),
+        })
+    }
+    /// Derive a new key at the given BIP-32 path, every path component must be hardened.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
     pub fn derive(&mut self, path: Vec<u32>, cvc: String) -> Result<DeriveResponse, Error> {
-        // set most significant bit to 1 to represent hardened path steps
-        let path = path.iter().map(|p| p ^ (1 << 31)).collect();
-        let app_nonce = rand_nonce();
-        let (_, epubkey, xcvc) = self.calc_ekeys_xcvc(cvc, &DeriveCommand::name());
-        let cmd = DeriveCommand::for_tapsigner(app_nonce.clone(), path, epubkey, xcvc);
-        let derive_response: Result<DeriveResponse, Error> = self.transport.transmit(cmd);
+        if let Some(index) = path.iter().find(|p| *p & HARDENED == 0) {
+            return Err(Error::InvalidPath(format!(
+                "TAPSIGNER only supports hardened derivation, index {} is not hardened",
+                index
+            )));
+        }
+        let app_nonce = self.app_nonce();
+        let (_, epubkey, xcvc) = self.calc_ekeys_xcvc(cvc, &DeriveCommand::name())?;
+        let cmd = DeriveCommand::for_tapsigner(app_nonce.clone(), path, epubkey, xcvc)?;
+        let derive_response: Result<DeriveResponse, Error> = transmit_auth(self, cmd);
         if let Ok(response) = &derive_response {
             let card_nonce = self.card_nonce();
             let sig = &response.sig;
@@ -137,17 +544,448 @@ impl<T: CkTransport> TapSigner<T> {
             message_bytes.extend(&response.chain_code);
             let message = Message::from_hashed_data::<sha256::Hash>(message_bytes.as_slice());
             let signature = Signature::from_compact(sig.as_slice())?;
-            let pubkey = PublicKey::from_slice(response.master_pubkey.as_slice())?;
-            // TODO fix verify when a derivation path is used, currently only works if no path given
+            // signed by the derived key, or by the master key if no path was given
+            let pubkey = match response.pubkey()? {
+                Some(pubkey) => pubkey,
+                None => response.master_pubkey()?,
+            };
             self.secp().verify_ecdsa(&message, &signature, &pubkey)?;
-            self.set_card_nonce(response.card_nonce.clone());
+            self.update_card_nonce(response.card_nonce.clone())?;
+            self.account_xpub = None;
         }
         derive_response
     }
+
+    /// Get the BIP-32 xpub of the master key (`m`) or of the key at the current derivation path.
+    /// The current path xpub is kept to check the keys `sign` responses are signed with.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub fn xpub(&mut self, master: bool, cvc: String) -> Result<Xpub, Error> {
+        let (_, epubkey, xcvc) = self.calc_ekeys_xcvc(cvc, &XpubCommand::name())?;
+        let xpub_command = XpubCommand::new(master, epubkey, xcvc);
+        let xpub_response: XpubResponse = transmit_auth(self, xpub_command)?;
+        self.update_card_nonce(xpub_response.card_nonce.clone())?;
+        let mut xpub = xpub_response.xpub()?;
+        xpub.network = self.render_network();
+        if !master {
+            self.account_xpub = Some(xpub);
+        }
+        Ok(xpub)
+    }
+
+    /// Change the CVC from `old_cvc` to `new_cvc`, which must be 6 to 32 bytes long.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub fn change_cvc(
+        &mut self,
+        old_cvc: String,
+        new_cvc: String,
+    ) -> Result<ChangeResponse, Error> {
+        check_cvc(&new_cvc)?;
+        let (eprivkey, epubkey, xcvc) = self.calc_ekeys_xcvc(old_cvc, &ChangeCommand::name())?;
+        let new_cvc = Zeroizing::new(new_cvc);
+        let session_key =
+            Zeroizing::new(SharedSecret::new(self.pubkey(), &eprivkey).secret_bytes());
+        let data = xor_session_key(new_cvc.as_bytes(), &session_key[..]);
+        let change_command = ChangeCommand::new(data, epubkey, xcvc);
+        let change_response: Result<ChangeResponse, Error> = transmit_auth(self, change_command);
+        if let Ok(response) = &change_response {
+            self.update_card_nonce(response.card_nonce.clone())?;
+        }
+        change_response
+    }
+
+    /// Get the encrypted backup of the master private key, see [`BackupCommand`] for the format.
+    ///
+    /// The card only allows a limited number of backups, `num_backups` counts the ones made.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub fn backup(&mut self, cvc: String) -> Result<Vec<u8>, Error> {
+        let (_, epubkey, xcvc) = self.calc_ekeys_xcvc(cvc, &BackupCommand::name())?;
+        let backup_command = BackupCommand::new(epubkey, xcvc);
+        let backup_response: BackupResponse = transmit_auth(self, backup_command)?;
+        self.update_card_nonce(backup_response.card_nonce.clone())?;
+        self.num_backups = Some(self.num_backups.unwrap_or_default() + 1);
+        Ok(backup_response.data)
+    }
+
+    /// The BIP-32 fingerprint of the master key (`m`), computed from the master xpub.
+    ///
+    /// An account xpub only has the fingerprint of its parent, which is the master fingerprint
+    /// only if the xpub is at depth 1, so use this for descriptors and PSBT key origins. The value
+    /// is cached after the first call since it doesn't change for the card.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub fn master_fingerprint(&mut self, cvc: String) -> Result<Fingerprint, Error> {
+        if let Some(fingerprint) = self.master_fingerprint {
+            return Ok(fingerprint);
+        }
+        let fingerprint = self.xpub(true, cvc)?.fingerprint();
+        self.master_fingerprint = Some(fingerprint);
+        Ok(fingerprint)
+    }
+
+    /// Output descriptor for the receive addresses at the card's current derivation path, eg.
+    /// `wpkh([fingerprint/84'/0'/0']xpub/0/*)#checksum`, ready to import into a wallet.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub fn descriptor(&mut self, script_type: ScriptType, cvc: String) -> Result<String, Error> {
+        let path = self
+            .derivation_path()?
+            .ok_or_else(|| Error::InvalidPath("Card is not set up".to_string()))?;
+        let fingerprint = self.master_fingerprint(cvc.clone())?;
+        let xpub = self.xpub(false, cvc)?;
+
+        let origin = path.to_string();
+        let origin = origin.trim_start_matches('m');
+        let descriptor = format!(
+            "{}([{}{}]{}/0/*)",
+            script_type.name(),
+            fingerprint,
+            origin,
+            xpub
+        );
+        let checksum = descriptor_checksum(&descriptor)?;
+        Ok(format!("{}#{}", descriptor, checksum))
+    }
+
+    /// The native segwit receive address at `<path>/0/<index>`, one of the addresses of the
+    /// `wpkh` descriptor. The key is derived from the `xpub` at the current path, a hardened
+    /// `index` fails with `Error::InvalidPath` before anything is sent.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub fn address(&mut self, index: u32, cvc: String) -> Result<Address, Error> {
+        let subpath = [
+            ChildNumber::from_normal_idx(0)?,
+            ChildNumber::from_normal_idx(index)?,
+        ];
+        let xpub = self.xpub(false, cvc)?;
+        let pubkey = xpub.derive_pub(self.secp(), &subpath)?.public_key;
+        Ok(Address::p2wpkh(
+            &bitcoin::PublicKey::new(pubkey),
+            self.render_network(),
+        )?)
+    }
+
+    /// Sign a 32 byte digest with the key at the card's derivation path plus the optional
+    /// `subpath`, eg. `[0, 5]` for the sixth receive address. More than two components or a
+    /// hardened one fail with `Error::InvalidSubpath` before anything is sent. The card answers
+    /// error 205 "unlucky number" about once in 256 signs, the command is then resent, up to five
+    /// attempts in total before failing with `Error::SignRetryExhausted`.
+    ///
+    /// The signature must verify for the returned pubkey over `digest`, else `Error::BadSignature`.
+    /// Once `xpub` was read for the current path, the pubkey must also be the one derived from it
+    /// at `subpath`, else `Error::DerivationMismatch`.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub fn sign(
+        &mut self,
+        digest: Vec<u8>,
+        subpath: Option<Vec<u32>>,
+        cvc: String,
+    ) -> Result<SignResponse, Error> {
+        if digest.len() != 32 {
+            return Err(Error::InvalidDigest(format!(
+                "Digest must be 32 bytes, got {}",
+                digest.len()
+            )));
+        }
+        if let Some(subpath) = &subpath {
+            check_subpath(subpath)?;
+        }
+
+        // the card returns error 205 (unlucky number) if it picked an unusable nonce for the
+        // signature, in which case the command is sent again. `transmit_auth` replaced the session
+        // keys on the error, so the resent command has a new ephemeral key and xcvc
+        for _ in 0..SIGN_ATTEMPTS {
+            let (_, epubkey, xcvc) = self.calc_ekeys_xcvc(cvc.clone(), &SignCommand::name())?;
+            let sign_command =
+                SignCommand::for_tapsigner(subpath.clone(), digest.clone(), epubkey, xcvc);
+            let sign_response: Result<SignResponse, Error> = transmit_auth(self, sign_command);
+            match sign_response {
+                Ok(response) => {
+                    self.update_card_nonce(response.card_nonce.clone())?;
+                    self.check_sign_response(&response, &digest, subpath.as_deref())?;
+                    return Ok(response);
+                }
+                Err(e) if e.cktap_code() == Some(CkTapErrorCode::UnluckyNumber) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Err(Error::SignRetryExhausted {
+            attempts: SIGN_ATTEMPTS,
+        })
+    }
+
+    fn check_sign_response(
+        &self,
+        response: &SignResponse,
+        digest: &[u8],
+        subpath: Option<&[u32]>,
+    ) -> Result<(), Error> {
+        let pubkey = response.pubkey()?;
+        let mut sig = response.signature()?;
+        sig.normalize_s();
+        let msg = Message::from_digest_slice(digest)?;
+        self.secp().verify_ecdsa(&msg, &sig, &pubkey).map_err(|_| {
+            Error::BadSignature(
+                "Sign response signature does not verify for its pubkey".to_string(),
+            )
+        })?;
+
+        if let Some(xpub) = &self.account_xpub {
+            let path: Vec<ChildNumber> = subpath
+                .unwrap_or_default()
+                .iter()
+                .map(|index| ChildNumber::from(*index))
+                .collect();
+            let derived = xpub.derive_pub(self.secp(), &path)?;
+            if !ct_eq(&derived.public_key.serialize(), &pubkey.serialize()) {
+                return Err(Error::DerivationMismatch(format!(
+                    "Card signed with {}, expected {} at subpath {:?}",
+                    pubkey,
+                    derived.public_key,
+                    subpath.unwrap_or_default()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Sign a message with the Bitcoin signed message prefix, returning the base64 encoded compact
+    /// recoverable signature accepted by `verifymessage` for the key's P2PKH address.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub fn sign_message(
+        &mut self,
+        message: &str,
+        subpath: Option<Vec<u32>>,
+        cvc: String,
+    ) -> Result<String, Error> {
+        let msg_hash = signed_msg_hash(message);
+        let response = self.sign(msg_hash.to_byte_array().to_vec(), subpath, cvc)?;
+        let msg = Message::from_digest(msg_hash.to_byte_array());
+        let id = recover_id(&msg, &response.sig, &response.pubkey()?)?;
+        let signature = RecoverableSignature::from_compact(&response.sig, id)?;
+        Ok(MessageSignature::new(signature, true).to_base64())
+    }
+
+    /// Sign the PSBT inputs with a BIP-32 derivation from this card, legacy and segwit v0 inputs
+    /// are supported. Inputs for other keys are skipped, returns the number of signatures added.
+    ///
+    /// The card only makes ECDSA signatures, so a taproot input for this card fails with
+    /// `Error::UnsupportedInputType` before anything is signed.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub fn sign_psbt(&mut self, psbt: &mut Psbt, cvc: String) -> Result<usize, Error> {
+        let fingerprint = self.master_fingerprint(cvc.clone())?;
+        let card_path = self
+            .derivation_path()?
+            .ok_or_else(|| Error::InvalidPath("Card is not set up".to_string()))?;
+        check_no_taproot_inputs(psbt, fingerprint, &card_path)?;
+
+        let mut cache = SighashCache::new(psbt.unsigned_tx.clone());
+        let mut signed = 0;
+        for index in 0..psbt.inputs.len() {
+            let keys: Vec<(PublicKey, Vec<u32>)> = psbt.inputs[index]
+                .bip32_derivation
+                .iter()
+                .filter(|(_, (key_fingerprint, _))| *key_fingerprint == fingerprint)
+                .filter_map(|(pubkey, (_, path))| {
+                    card_subpath(&card_path, path).map(|subpath| (*pubkey, subpath))
+                })
+                .collect();
+            for (pubkey, subpath) in keys {
+                let (msg, hash_ty) = psbt
+                    .sighash_ecdsa(index, &mut cache)
+                    .map_err(|e| Error::InvalidPsbt(e.to_string()))?;
+                let subpath = if subpath.is_empty() {
+                    None
+                } else {
+                    Some(subpath)
+                };
+                let response = self.sign(msg.as_ref().to_vec(), subpath, cvc.clone())?;
+                let mut sig = response.signature()?;
+                sig.normalize_s();
+                self.secp().verify_ecdsa(&msg, &sig, &pubkey)?;
+                psbt.inputs[index].partial_sigs.insert(
+                    bitcoin::PublicKey::new(pubkey),
+                    ecdsa::Signature { sig, hash_ty },
+                );
+                signed += 1;
+            }
+        }
+        Ok(signed)
+    }
+}
+
+/// Taproot inputs need a schnorr signature, which the card can't make. An input is for the card
+/// if one of its key origins has the card's fingerprint and a path the card can sign for.
+#[cfg(feature = "std")]
+fn check_no_taproot_inputs(
+    psbt: &Psbt,
+    fingerprint: Fingerprint,
+    card_path: &DerivationPath,
+) -> Result<(), Error> {
+    let is_card_key = |(key_fingerprint, path): &(Fingerprint, DerivationPath)| {
+        *key_fingerprint == fingerprint && card_subpath(card_path, path).is_some()
+    };
+    for (index, input) in psbt.inputs.iter().enumerate() {
+        let spends_p2tr = input
+            .witness_utxo
+            .as_ref()
+            .map_or(false, |utxo| utxo.script_pubkey.is_p2tr());
+        let taproot_key = input
+            .tap_key_origins
+            .values()
+            .any(|(_, origin)| is_card_key(origin));
+        let ecdsa_key = input.bip32_derivation.values().any(is_card_key);
+        if taproot_key || (spends_p2tr && ecdsa_key) {
+            return Err(Error::UnsupportedInputType(format!(
+                "Input {} is taproot, the card only makes ECDSA signatures",
+                index
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// The card signs for at most two non-hardened components below its derivation path.
+#[cfg(feature = "std")]
+fn check_subpath(subpath: &[u32]) -> Result<(), Error> {
+    if subpath.len() > 2 {
+        return Err(Error::InvalidSubpath(format!(
+            "At most 2 components are allowed, got {}",
+            subpath.len()
+        )));
+    }
+    if let Some(index) = subpath.iter().find(|index| *index & HARDENED != 0) {
+        return Err(Error::InvalidSubpath(format!(
+            "Index {}' is hardened",
+            index ^ HARDENED
+        )));
+    }
+    Ok(())
+}
+
+/// The subpath of `path` below the card's derivation path, the card can sign for at most two
+/// more non-hardened components.
+#[cfg(feature = "std")]
+fn card_subpath(card_path: &DerivationPath, path: &DerivationPath) -> Option<Vec<u32>> {
+    let subpath = path.as_ref().strip_prefix(card_path.as_ref())?;
+    if subpath.len() > 2 || subpath.iter().any(|child| child.is_hardened()) {
+        return None;
+    }
+    Some(subpath.iter().map(|child| u32::from(*child)).collect())
+}
+
+/// Result of `TapSigner::init`.
+#[cfg(feature = "std")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InitResult {
+    /// card public key reported after setup
+    pub pubkey: PublicKey,
+    /// derivation path the card set, `m/84'/0'/0'`
+    pub path: DerivationPath,
+    /// nonce for the next command
+    pub card_nonce: Vec<u8>,
+}
+
+/// Script type of a TAPSIGNER output descriptor.
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScriptType {
+    /// native segwit v0, `wpkh(...)`
+    Wpkh,
+    /// taproot key path, `tr(...)`
+    Tr,
+}
+
+#[cfg(feature = "std")]
+impl ScriptType {
+    fn name(&self) -> &'static str {
+        match self {
+            ScriptType::Wpkh => "wpkh",
+            ScriptType::Tr => "tr",
+        }
+    }
+}
+
+/// Descriptor checksum, see BIP-380.
+#[cfg(feature = "std")]
+fn descriptor_checksum(descriptor: &str) -> Result<String, Error> {
+    const INPUT_CHARSET: &str = "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+    const CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+    const GENERATOR: [u64; 5] = [
+        0xf5dee51989,
+        0xa9fdca3312,
+        0x1bab10e32d,
+        0x3706b1677a,
+        0x644d626ffd,
+    ];
+
+    fn polymod(c: u64, val: u64) -> u64 {
+        let c0 = c >> 35;
+        let mut c = ((c & 0x7ffffffff) << 5) ^ val;
+        for (i, generator) in GENERATOR.iter().enumerate() {
+            if c0 & (1 << i) != 0 {
+                c ^= generator;
+            }
+        }
+        c
+    }
+
+    let mut c = 1;
+    let mut cls = 0;
+    let mut cls_count = 0;
+    for ch in descriptor.chars() {
+        let pos = INPUT_CHARSET
+            .find(ch)
+            .ok_or_else(|| Error::InvalidPath(format!("Invalid descriptor character {:?}", ch)))?
+            as u64;
+        c = polymod(c, pos & 31);
+        cls = cls * 3 + (pos >> 5);
+        cls_count += 1;
+        if cls_count == 3 {
+            c = polymod(c, cls);
+            cls = 0;
+            cls_count = 0;
+        }
+    }
+    if cls_count > 0 {
+        c = polymod(c, cls);
+    }
+    for _ in 0..8 {
+        c = polymod(c, 0);
+    }
+    c ^= 1;
+    Ok((0..8)
+        .map(|j| CHECKSUM_CHARSET[((c >> (5 * (7 - j))) & 31) as usize] as char)
+        .collect())
+}
+
+/// The card returns 64 byte compact signatures, find the recovery id that recovers
+/// `expected_pubkey` from `sig` over `digest`. An error means the signature is corrupt or from
+/// another key.
+#[cfg(feature = "std")]
+pub fn recover_id(
+    digest: &Message,
+    sig: &[u8],
+    expected_pubkey: &PublicKey,
+) -> Result<RecoveryId, Error> {
+    let secp = Secp256k1::verification_only();
+    for id in 0..4 {
+        let id = RecoveryId::from_i32(id)?;
+        let signature = RecoverableSignature::from_compact(sig, id)?;
+        if let Ok(pubkey) = secp.recover_ecdsa(digest, &signature) {
+            if ct_eq(&pubkey.serialize(), &expected_pubkey.serialize()) {
+                return Ok(id);
+            }
+        }
+    }
+    Err(Error::BadSignature(
+        "Signature does not recover the signing pubkey".to_string(),
+    ))
 }
 
+#[cfg(feature = "std")]
 impl<T: CkTransport> Wait<T> for TapSigner<T> {}
 
+#[cfg(feature = "std")]
+impl<T: CkTransport> Nfc<T> for TapSigner<T> {}
+
+#[cfg(feature = "std")]
 impl<T: CkTransport> Read<T> for TapSigner<T> {
     fn requires_auth(&self) -> bool {
         true
@@ -158,16 +996,24 @@ impl<T: CkTransport> Read<T> for TapSigner<T> {
     }
 }
 
+#[cfg(feature = "std")]
 impl<T: CkTransport> Certificate<T> for TapSigner<T> {
-    fn message_digest(&mut self, card_nonce: Vec<u8>, app_nonce: Vec<u8>) -> Message {
+    fn message_digest(
+        &mut self,
+        card_nonce: Vec<u8>,
+        app_nonce: Vec<u8>,
+    ) -> Result<Message, Error> {
         let mut message_bytes: Vec<u8> = Vec::new();
         message_bytes.extend("OPENDIME".as_bytes());
         message_bytes.extend(card_nonce);
         message_bytes.extend(app_nonce);
-        Message::from_hashed_data::<sha256::Hash>(message_bytes.as_slice())
+        Ok(Message::from_hashed_data::<sha256::Hash>(
+            message_bytes.as_slice(),
+        ))
     }
 }
 
+#[cfg(feature = "std")]
 impl<T: CkTransport> Debug for TapSigner<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("TapSigner")
@@ -179,10 +1025,15 @@ impl<T: CkTransport> Debug for TapSigner<T> {
             .field("pubkey", &self.pubkey)
             .field("card_nonce", &self.card_nonce)
             .field("auth_delay", &self.auth_delay)
+            .field("bad_cvc_count", &self.bad_cvc_count)
+            .field("network", &self.network)
+            .field("network_override", &self.network_override)
             .finish()
     }
 }
 
+#[cfg(feature = "std")]
+#[derive(Clone)]
 pub struct SatsCard<T: CkTransport> {
     pub transport: T,
     pub secp: Secp256k1<All>,
@@ -194,8 +1045,14 @@ pub struct SatsCard<T: CkTransport> {
     pub pubkey: PublicKey,
     pub card_nonce: Vec<u8>, // 16 bytes
     pub auth_delay: Option<usize>,
+    pub bad_cvc_count: usize,
+    pub network: Network,
+    network_override: Option<Network>,
+    session_keys: Option<SessionKeys>,
+    rng: CardRng,
 }
 
+#[cfg(feature = "std")]
 impl<T: CkTransport> Authentication<T> for SatsCard<T> {
     fn secp(&self) -> &Secp256k1<All> {
         &self.secp
@@ -221,17 +1078,36 @@ impl<T: CkTransport> Authentication<T> for SatsCard<T> {
         self.auth_delay = auth_delay;
     }
 
+    fn bad_cvc_count(&self) -> usize {
+        self.bad_cvc_count
+    }
+
+    fn set_bad_cvc_count(&mut self, bad_cvc_count: usize) {
+        self.bad_cvc_count = bad_cvc_count;
+    }
+
+    fn session_keys(&self) -> Option<&SessionKeys> {
+        self.session_keys.as_ref()
+    }
+
+    fn set_session_keys(&mut self, session_keys: SessionKeys) {
+        self.session_keys = Some(session_keys);
+    }
+
     fn transport(&self) -> &T {
         &self.transport
     }
+
+    fn rng(&self) -> &CardRng {
+        &self.rng
+    }
 }
 
+#[cfg(feature = "std")]
 impl<T: CkTransport> SatsCard<T> {
     pub fn from_status(transport: T, status_response: StatusResponse) -> Result<Self, Error> {
-        let pubkey = status_response.pubkey.as_slice(); // TODO verify is 33 bytes?
-        let pubkey = PublicKey::from_slice(pubkey)
-            .map_err(|e| Error::CiborValue(e.to_string()))
-            .unwrap();
+        let pubkey = status_response.pubkey()?;
+        let network = status_response.network();
         let slots = status_response
             .slots
             .ok_or_else(|| Error::CiborValue("Missing slots".to_string()))?;
@@ -244,37 +1120,128 @@ impl<T: CkTransport> SatsCard<T> {
             pubkey,
             card_nonce: status_response.card_nonce,
             auth_delay: status_response.auth_delay,
+            bad_cvc_count: 0,
             slots,
             addr: status_response.addr,
+            network,
+            network_override: None,
+            session_keys: None,
+            rng: CardRng::default(),
         })
     }
 
+    /// Block height at the card's production, see `approx_block_time`. Fails with
+    /// `Error::MalformedResponse` if `birth` is too large to be a block height.
+    pub fn birth_height(&self) -> Result<u32, Error> {
+        u32::try_from(self.birth).map_err(|_| Error::MalformedResponse { field: "birth" })
+    }
+
+    /// Render addresses, xpubs and descriptors for `network` instead of the card's own `testnet`
+    /// flag. This is client side only, nothing changes on the card and signing is unaffected.
+    pub fn with_network(mut self, network: Network) -> Self {
+        self.network_override = Some(network);
+        self
+    }
+
+    /// The network used to render addresses, xpubs and descriptors, see `with_network`.
+    pub fn render_network(&self) -> Network {
+        self.network_override.unwrap_or(self.network)
+    }
+
+    /// Pick app nonces and the session's ephemeral key with `rng` instead of `thread_rng`, see
+    /// `CardRng`.
+    pub fn with_rng<R: RngCore + Send + 'static>(mut self, rng: R) -> Self {
+        self.rng = CardRng::new(rng);
+        self
+    }
+
+    /// The slot currently in use, from the status `slots`. `read`, `dump`, `unseal` and
+    /// `new_slot` all work relative to it, it is only the first slot on a new card.
+    pub fn active_slot(&self) -> Slot {
+        Slot(self.slots.0)
+    }
+
+    /// State of the active slot from the status `addr`, which the card only reports while the
+    /// slot is sealed. Cleared by `unseal`, after `new_slot` call `status` to refresh it.
+    pub fn lifecycle(&self) -> CardLifecycle {
+        let (active_slot, num_slots) = self.slots;
+        match &self.addr {
+            Some(_) => CardLifecycle::Active,
+            None if active_slot + 1 >= num_slots => CardLifecycle::Used,
+            None => CardLifecycle::Unsealed,
+        }
+    }
+
+    /// Slot `index`, checked against the card's slot count.
+    pub fn slot_at(&self, index: u8) -> Result<Slot, Error> {
+        Slot::new(index, self.slots.1)
+    }
+
+    /// The slot `new_slot` will accept, checked with a `dump` of the active slot: the active slot
+    /// if it is unused, or the following slot if the active slot is unsealed. Fails with
+    /// `Error::InvalidSlot` if the active slot is still sealed or all slots have been used.
+    pub fn next_slot(&mut self) -> Result<Slot, Error> {
+        let (active_slot, num_slots) = self.slots;
+        let next_slot = match self.dump(self.active_slot(), None)? {
+            SlotState::Sealed { .. } => {
+                return Err(Error::InvalidSlot(format!(
+                    "Current slot {} must be unsealed before picking a new slot",
+                    active_slot
+                )))
+            }
+            SlotState::Unused { .. } => active_slot,
+            SlotState::Unsealed { .. } => active_slot + 1,
+        };
+        if next_slot >= num_slots {
+            return Err(Error::InvalidSlot("All slots have been used".to_string()));
+        }
+        self.slot_at(next_slot)
+    }
+
+    /// Pick a new private key and start a fresh slot. The given slot must be the one `next_slot`
+    /// returns. A sealed active slot must be unsealed first.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(slot = %slot))
+    )]
     pub fn new_slot(
         &mut self,
-        slot: u8,
+        slot: Slot,
         chain_code: Option<Vec<u8>>,
         cvc: String,
     ) -> Result<NewResponse, Error> {
-        let (_, epubkey, xcvc) = self.calc_ekeys_xcvc(cvc, &NewCommand::name());
+        if let Some(chain_code) = &chain_code {
+            check_chain_code(chain_code)?;
+        }
+        let next_slot = self.next_slot()?;
+        if slot != next_slot {
+            return Err(Error::InvalidSlot(format!(
+                "Slot {} can not be picked, next available slot is {}",
+                slot, next_slot
+            )));
+        }
+
+        let (_, epubkey, xcvc) = self.calc_ekeys_xcvc(cvc, &NewCommand::name())?;
         let epubkey = epubkey.serialize().to_vec();
-        let new_command = NewCommand::new(Some(slot), chain_code, epubkey, xcvc);
-        let new_response: Result<NewResponse, Error> = self.transport.transmit(new_command);
+        let new_command = NewCommand::new(Some(slot.index()), chain_code, epubkey, xcvc);
+        let new_response: Result<NewResponse, Error> = transmit_auth(self, new_command);
         if let Ok(response) = &new_response {
-            self.card_nonce = response.card_nonce.clone();
+            self.update_card_nonce(response.card_nonce.clone())?;
             self.slots.0 = response.slot;
         }
         new_response
     }
 
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
     pub fn derive(&mut self) -> Result<DeriveResponse, Error> {
-        let nonce = rand_nonce();
+        let nonce = self.app_nonce();
         let card_nonce = self.card_nonce().clone();
 
-        let cmd = DeriveCommand::for_satscard(nonce.clone());
+        let cmd = DeriveCommand::for_satscard(nonce.clone())?;
         let resp: Result<DeriveResponse, Error> = self.transport().transmit(cmd);
 
         if let Ok(r) = &resp {
-            self.set_card_nonce(r.card_nonce.clone());
+            self.update_card_nonce(r.card_nonce.clone())?;
 
             // Verify signature
             let mut message_bytes: Vec<u8> = Vec::new();
@@ -283,54 +1250,414 @@ impl<T: CkTransport> SatsCard<T> {
             message_bytes.extend(nonce);
             message_bytes.extend(r.chain_code.clone());
             let message = Message::from_hashed_data::<sha256::Hash>(message_bytes.as_slice());
-            let signature = Signature::from_compact(r.sig.as_slice())
-                .expect("Failed to construct ECDSA signature from check response");
-            let pubkey = PublicKey::from_slice(r.master_pubkey.as_slice())?;
+            let signature = Signature::from_compact(r.sig.as_slice())?;
+            let pubkey = r.master_pubkey()?;
             self.secp().verify_ecdsa(&message, &signature, &pubkey)?;
         }
         resp
     }
 
-    pub fn unseal(&mut self, slot: u8, cvc: String) -> Result<UnsealResponse, Error> {
-        let (_, epubkey, xcvc) = self.calc_ekeys_xcvc(cvc, &UnsealCommand::name());
-        let epubkey = epubkey.serialize().to_vec();
-        let unseal_command = UnsealCommand::new(slot, epubkey, xcvc);
-        let unseal_response: Result<UnsealResponse, Error> =
-            self.transport.transmit(unseal_command);
-        if let Ok(response) = &unseal_response {
-            self.set_card_nonce(response.card_nonce.clone())
+    /// Read the active slot's pubkey and address, no CVC is needed. The card signs the nonces and
+    /// the slot number with the slot key, so a response for any other slot fails verification.
+    /// The low level response is still available from `read`.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub fn read_slot(&mut self) -> Result<ReadResult, Error> {
+        let slot = self.active_slot();
+        let pubkey = self.read(None)?.pubkey(None)?;
+        let bitcoin_pubkey = bitcoin::PublicKey::new(pubkey);
+        let verified = match &self.addr {
+            Some(partial) => {
+                let card_address = Address::p2wpkh(&bitcoin_pubkey, self.network)?;
+                matches_partial_address(&card_address.to_string(), partial)
+            }
+            None => false,
+        };
+        Ok(ReadResult {
+            slot,
+            pubkey,
+            address: Address::p2wpkh(&bitcoin_pubkey, self.render_network())?,
+            verified,
+        })
+    }
+
+    /// Confirm the payment address of the active slot.
+    ///
+    /// The card signs its master pubkey and chain code with `derive`, the payment pubkey is then
+    /// derived at `m/0` and must equal the slot pubkey from `read`. The slot must be sealed, an
+    /// unsealed slot can't prove its address this way, use `dump` with the CVC instead.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub fn derive_address(&mut self) -> Result<Address, Error> {
+        let derive_response = self.derive().map_err(|e| match e.cktap_code() {
+            Some(CkTapErrorCode::InvalidState) => Error::InvalidSlot(format!(
+                "Slot {} is unsealed or unused, its address can't be derived",
+                self.slots.0
+            )),
+            _ => e,
+        })?;
+        let chain_code: [u8; 32] = derive_response
+            .chain_code
+            .as_slice()
+            .try_into()
+            .map_err(|_| Error::CiborValue("Chain code must be 32 bytes".to_string()))?;
+        let master = Xpub {
+            network: self.network,
+            depth: 0,
+            parent_fingerprint: Default::default(),
+            child_number: ChildNumber::Normal { index: 0 },
+            public_key: derive_response.master_pubkey()?,
+            chain_code: chain_code.into(),
+        };
+        let derived = master.derive_pub(self.secp(), &[ChildNumber::Normal { index: 0 }])?;
+
+        let pubkey = self.read(None)?.pubkey(None)?;
+        if !ct_eq(&derived.public_key.serialize(), &pubkey.serialize()) {
+            return Err(Error::InvalidAddress(
+                "Slot pubkey does not follow from the card's master pubkey and chain code"
+                    .to_string(),
+            ));
         }
-        unseal_response
+        Ok(Address::p2wpkh(
+            &bitcoin::PublicKey::new(pubkey),
+            self.render_network(),
+        )?)
     }
 
-    pub fn dump(&self, slot: usize, cvc: Option<String>) -> Result<DumpResponse, Error> {
-        let epubkey_xcvc = cvc.map(|cvc| {
-            let (_, epubkey, xcvc) = self.calc_ekeys_xcvc(cvc, &DumpCommand::name());
-            (epubkey, xcvc)
-        });
+    /// Unseal the current slot, the returned private key is decrypted with the session key.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(slot = %slot))
+    )]
+    pub fn unseal(&mut self, slot: Slot, cvc: String) -> Result<UnsealResponse, Error> {
+        if slot != self.active_slot() {
+            return Err(Error::InvalidSlot(format!(
+                "Only the current slot {} can be unsealed",
+                self.slots.0
+            )));
+        }
+        let (eprivkey, epubkey, xcvc) = self.calc_ekeys_xcvc(cvc, &UnsealCommand::name())?;
+        let session_key =
+            Zeroizing::new(SharedSecret::new(self.pubkey(), &eprivkey).secret_bytes());
+        let epubkey = epubkey.serialize().to_vec();
+        let unseal_command = UnsealCommand::new(slot.index(), epubkey, xcvc);
+        let mut unseal_response: UnsealResponse = transmit_auth(self, unseal_command)?;
+        self.update_card_nonce(unseal_response.card_nonce.clone())?;
+        self.addr = None;
+        unseal_response.privkey = xor_session_key(&unseal_response.privkey, &session_key[..]);
+        Ok(unseal_response)
+    }
+
+    /// Dump the state of a slot. With the CVC, an unsealed slot's private key is decrypted.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(slot = %slot))
+    )]
+    pub fn dump(&mut self, slot: Slot, cvc: Option<String>) -> Result<SlotState, Error> {
+        let cvc = cvc.map(Zeroizing::new);
+        self.dump_slot(slot.index() as usize, cvc.as_deref().map(String::as_str))
+    }
 
-        let (epubkey, xcvc) = epubkey_xcvc
-            .map(|(epubkey, xcvc)| (Some(epubkey.serialize().to_vec()), Some(xcvc)))
-            .unwrap_or((None, None));
+    /// Dump the state of every slot, eg. to audit a used card. The session's ephemeral key is used
+    /// for all the dump commands, only the xcvc changes with each new card nonce.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub fn dump_all_slots(&mut self, cvc: Option<String>) -> Result<Vec<SlotState>, Error> {
+        let cvc = cvc.map(Zeroizing::new);
+        (0..self.slots.1 as usize)
+            .map(|slot| self.dump_slot(slot, cvc.as_deref().map(String::as_str)))
+            .collect()
+    }
+
+    /// Every used slot with its state and address, eg. to audit a secondhand card. Unsealed slot
+    /// addresses come from the decrypted private key, the sealed slot's from its dumped pubkey or
+    /// else the pubkey verified by `read`. Unused slots are left out.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub fn slot_addresses(
+        &mut self,
+        cvc: String,
+    ) -> Result<Vec<(Slot, SlotState, Address)>, Error> {
+        let mut addresses = Vec::new();
+        for state in self.dump_all_slots(Some(cvc))? {
+            let pubkey = match &state {
+                SlotState::Unused { .. } => continue,
+                SlotState::Unsealed {
+                    privkey: Some(privkey),
+                    ..
+                } => PublicKey::from_secret_key(self.secp(), privkey),
+                SlotState::Unsealed {
+                    pubkey: Some(pubkey),
+                    ..
+                }
+                | SlotState::Sealed {
+                    pubkey: Some(pubkey),
+                    ..
+                } => *pubkey,
+                SlotState::Sealed { slot, .. } if *slot == self.slots.0 as usize => {
+                    self.read(None)?.pubkey(None)?
+                }
+                SlotState::Sealed { slot, .. } | SlotState::Unsealed { slot, .. } => {
+                    return Err(Error::InvalidSlot(format!(
+                        "No public key for slot {}, its address can't be derived",
+                        slot
+                    )))
+                }
+            };
+            let slot = self.slot_at(state.slot() as u8)?;
+            let address = Address::p2wpkh(&bitcoin::PublicKey::new(pubkey), self.render_network())?;
+            addresses.push((slot, state, address));
+        }
+        Ok(addresses)
+    }
+
+    fn dump_slot(&mut self, slot: usize, cvc: Option<&str>) -> Result<SlotState, Error> {
+        let (epubkey, xcvc, session_key) = match cvc {
+            Some(cvc) => {
+                let (_, epubkey, xcvc) =
+                    self.calc_ekeys_xcvc(cvc.to_string(), &DumpCommand::name())?;
+                let session_key = self
+                    .session_keys()
+                    .map(|keys| Zeroizing::new(*keys.session_key()));
+                (Some(epubkey.serialize().to_vec()), Some(xcvc), session_key)
+            }
+            None => (None, None, None),
+        };
 
         let dump_command = DumpCommand::new(slot, epubkey, xcvc);
-        self.transport.transmit(dump_command)
+        let dump_response: DumpResponse = transmit_auth(self, dump_command)?;
+        self.update_card_nonce(dump_response.card_nonce.clone())?;
+        SlotState::from_dump(&dump_response, session_key.as_ref().map(|sk| &sk[..]))
     }
 
-    pub fn address(&mut self) -> Result<String, Error> {
-        // let pubkey = self.read(None).unwrap().pubkey;
-        // // let hrp = match self.testnet() { }
-        // let encoded = bech32::encode("bc", pubkey.to_base32(), Variant::Bech32);
-        // match encoded {
-        //     Ok(e) => Ok(e),
-        //     Err(_) => panic!("Failed to encoded pubkey")
-        // }
-        todo!()
+    /// Send the status command again, eg. after `new_slot` or a wait loop. The card nonce, auth
+    /// delay, `slots`, `addr` and `network` are updated from it.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub fn status(&mut self) -> Result<StatusResponse, Error> {
+        let status_response = refresh_status(self)?;
+        self.slots = status_response
+            .slots
+            .ok_or_else(|| Error::CiborValue("Missing slots".to_string()))?;
+        self.addr = status_response.addr.clone();
+        self.network = status_response.network();
+        Ok(status_response)
+    }
+
+    /// The full payment address of the active slot.
+    ///
+    /// The status is refreshed to get the card's partial `addr` and network, then the address is
+    /// derived from the slot pubkey verified by `read` and checked against the partial address.
+    /// Disagreement means the card or its responses were tampered with.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    pub fn address(&mut self) -> Result<Address, Error> {
+        self.status()?;
+        let partial = self.addr.clone().ok_or_else(|| {
+            Error::InvalidAddress(format!("No address for slot {}", self.slots.0))
+        })?;
+
+        let pubkey = self.read(None)?.pubkey(None)?;
+        let address = Address::p2wpkh(&bitcoin::PublicKey::new(pubkey), self.network)?;
+
+        if !matches_partial_address(&address.to_string(), &partial) {
+            return Err(Error::InvalidAddress(format!(
+                "Derived address {} does not match card address {}",
+                address, partial
+            )));
+        }
+        Ok(Address::p2wpkh(
+            &bitcoin::PublicKey::new(pubkey),
+            self.render_network(),
+        )?)
+    }
+}
+
+/// The card elides the middle of the address, e.g. `bc1qsqkhv___qf735wvl`, so compare only the
+/// visible prefix and suffix.
+#[cfg(feature = "std")]
+fn matches_partial_address(address: &str, partial: &str) -> bool {
+    let is_sep = |c: char| c == '_' || c == '.';
+    match (partial.find(is_sep), partial.rfind(is_sep)) {
+        (Some(start), Some(end)) => {
+            let (prefix, suffix) = (&partial[..start], &partial[end + 1..]);
+            address.len() >= prefix.len() + suffix.len()
+                && address.starts_with(prefix)
+                && address.ends_with(suffix)
+        }
+        _ => address == partial,
+    }
+}
+
+/// Verify a SATSCARD NFC url offline, without a connection to the card.
+///
+/// The `s` param is signed by the key of slot `o`. The pubkey is recovered from the signature,
+/// checked to pay to `address`, and `address` must end with the `r` param. Returns the slot
+/// pubkey, compare it with a known pubkey if one is available.
+#[cfg(feature = "std")]
+pub fn verify_slot_signature(url: &NfcUrl, address: &Address) -> Result<PublicKey, Error> {
+    let address_str = address.to_string();
+    match url.param("r") {
+        Some(suffix) if !suffix.is_empty() && address_str.ends_with(suffix) => {}
+        _ => {
+            return Err(Error::InvalidAddress(format!(
+                "NFC url does not match address {}",
+                address
+            )))
+        }
+    }
+
+    let secp = Secp256k1::verification_only();
+    let digest = url.signed_digest()?;
+    let sig = url.signature()?;
+    for id in 0..4 {
+        let signature = RecoverableSignature::from_compact(&sig, RecoveryId::from_i32(id)?)?;
+        if let Ok(pubkey) = secp.recover_ecdsa(&digest, &signature) {
+            let derived = Address::p2wpkh(&bitcoin::PublicKey::new(pubkey), *address.network())?;
+            if &derived == address {
+                return Ok(pubkey);
+            }
+        }
+    }
+    Err(Error::BadSignature(format!(
+        "NFC url signature is not from the key of {}",
+        address
+    )))
+}
+
+/// Index of a SATSCARD slot, validated against the card's slot count.
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Slot(u8);
+
+#[cfg(feature = "std")]
+impl Slot {
+    /// `num_slots` is the card's slot count, the second value of the status `slots`.
+    pub fn new(index: u8, num_slots: u8) -> Result<Self, Error> {
+        if index >= num_slots {
+            return Err(Error::InvalidSlot(format!(
+                "Slot {} is out of range, the card has {} slots",
+                index, num_slots
+            )));
+        }
+        Ok(Slot(index))
+    }
+
+    pub fn index(&self) -> u8 {
+        self.0
+    }
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for Slot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
     }
 }
 
+/// State of a SATSCARD slot, from `SatsCard::dump`.
+#[cfg(feature = "std")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SlotState {
+    /// The slot is in use and its private key has not been revealed.
+    Sealed {
+        slot: usize,
+        pubkey: Option<PublicKey>,
+    },
+    /// The slot was unsealed. Keys and chain code are only included if the CVC was given, then
+    /// `privkey` is the decrypted private key for spending.
+    Unsealed {
+        slot: usize,
+        pubkey: Option<PublicKey>,
+        addr: Option<String>,
+        privkey: Option<SecretKey>,
+        chain_code: Option<Vec<u8>>,
+        master_pk: Option<SecretKey>,
+        tampered: bool,
+    },
+    /// The slot has not been set up yet.
+    Unused { slot: usize },
+}
+
+#[cfg(feature = "std")]
+impl SlotState {
+    /// Index of the slot this state is for.
+    pub fn slot(&self) -> usize {
+        match self {
+            SlotState::Sealed { slot, .. }
+            | SlotState::Unsealed { slot, .. }
+            | SlotState::Unused { slot } => *slot,
+        }
+    }
+
+    /// The decrypted private key of an unsealed slot as a compressed WIF, for sweeping the funds
+    /// from another wallet. `network` picks the version byte, pass the card's `render_network`.
+    /// `None` unless the slot was dumped with the CVC after it was unsealed.
+    pub fn to_wif(&self, network: Network) -> Option<String> {
+        match self {
+            SlotState::Unsealed {
+                privkey: Some(privkey),
+                ..
+            } => Some(PrivateKey::new(*privkey, network).to_wif()),
+            _ => None,
+        }
+    }
+
+    fn from_dump(response: &DumpResponse, session_key: Option<&[u8]>) -> Result<Self, Error> {
+        let slot = response.slot;
+        if response.sealed == Some(true) {
+            return Ok(SlotState::Sealed {
+                slot,
+                pubkey: response.pubkey()?,
+            });
+        }
+        if response.used == Some(false) {
+            return Ok(SlotState::Unused { slot });
+        }
+
+        let secret_key = |bytes: &[u8]| {
+            SecretKey::from_slice(bytes).map_err(|e| Error::CiborValue(e.to_string()))
+        };
+        let privkey = match (&response.privkey, session_key) {
+            (Some(encrypted), Some(session_key)) => {
+                let privkey = Zeroizing::new(xor_session_key(encrypted, session_key));
+                Some(secret_key(&privkey)?)
+            }
+            (Some(_), None) => {
+                return Err(Error::CiborValue(
+                    "Encrypted private key without a session key".to_string(),
+                ))
+            }
+            (None, _) => None,
+        };
+        Ok(SlotState::Unsealed {
+            slot,
+            pubkey: response.pubkey()?,
+            addr: response.addr.clone(),
+            privkey,
+            chain_code: response.chain_code.clone(),
+            master_pk: response.master_pk.as_deref().map(secret_key).transpose()?,
+            tampered: response.tampered.unwrap_or_default(),
+        })
+    }
+}
+
+/// Result of `SatsCard::read_slot`.
+#[cfg(feature = "std")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReadResult {
+    /// the active slot the pubkey was read from
+    pub slot: Slot,
+    /// slot pubkey, its signature over the nonces and slot number was verified
+    pub pubkey: PublicKey,
+    /// p2wpkh address of `pubkey` for the card's `render_network`
+    pub address: Address,
+    /// the address matches the partial `addr` from the card's last status, `false` if it doesn't
+    /// or the status had none because the slot is unsealed
+    pub verified: bool,
+}
+
+#[cfg(feature = "std")]
 impl<T: CkTransport> Wait<T> for SatsCard<T> {}
 
+#[cfg(feature = "std")]
+impl<T: CkTransport> Nfc<T> for SatsCard<T> {}
+
+#[cfg(feature = "std")]
 impl<T: CkTransport> Read<T> for SatsCard<T> {
     fn requires_auth(&self) -> bool {
         false
@@ -340,20 +1667,28 @@ impl<T: CkTransport> Read<T> for SatsCard<T> {
     }
 }
 
+#[cfg(feature = "std")]
 impl<T: CkTransport> Certificate<T> for SatsCard<T> {
-    fn message_digest(&mut self, card_nonce: Vec<u8>, app_nonce: Vec<u8>) -> Message {
+    fn message_digest(
+        &mut self,
+        card_nonce: Vec<u8>,
+        app_nonce: Vec<u8>,
+    ) -> Result<Message, Error> {
         let mut message_bytes: Vec<u8> = Vec::new();
         message_bytes.extend("OPENDIME".as_bytes());
         message_bytes.extend(card_nonce);
         message_bytes.extend(app_nonce);
         if self.ver != "0.9.0" {
-            let pubkey = self.read(None).unwrap().pubkey;
+            let pubkey = self.read(None)?.pubkey;
             message_bytes.extend(pubkey);
         }
-        Message::from_hashed_data::<sha256::Hash>(message_bytes.as_slice())
+        Ok(Message::from_hashed_data::<sha256::Hash>(
+            message_bytes.as_slice(),
+        ))
     }
 }
 
+#[cfg(feature = "std")]
 impl<T: CkTransport> Debug for SatsCard<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("SatsCard")
@@ -365,23 +1700,96 @@ impl<T: CkTransport> Debug for SatsCard<T> {
             .field("pubkey", &self.pubkey)
             .field("card_nonce", &self.card_nonce)
             .field("auth_delay", &self.auth_delay)
+            .field("bad_cvc_count", &self.bad_cvc_count)
+            .field("network", &self.network)
+            .field("network_override", &self.network_override)
             .finish()
     }
 }
 
 // utility functions
 
-pub fn rand_chaincode(rng: &mut ThreadRng) -> [u8; 32] {
+/// Decrypt bytes the card encrypted by XOR with the ECDH session key.
+#[cfg(feature = "std")]
+fn xor_session_key(encrypted: &[u8], session_key: &[u8]) -> Vec<u8> {
+    encrypted
+        .iter()
+        .zip(session_key)
+        .map(|(x, y)| x ^ y)
+        .collect()
+}
+
+/// Approximate time a block was mined, to show a card's birth height as a date.
+///
+/// Interpolates linearly between the genesis block and block 800,000, then assumes the 10 minute
+/// target interval, so expect an error of days rather than minutes.
+#[cfg(feature = "std")]
+pub fn approx_block_time(height: u32) -> SystemTime {
+    const GENESIS_TIME: u64 = 1_231_006_505;
+    const ANCHOR_HEIGHT: u64 = 800_000;
+    const ANCHOR_TIME: u64 = 1_690_168_629;
+
+    let height = u64::from(height);
+    let secs = if height <= ANCHOR_HEIGHT {
+        GENESIS_TIME + (ANCHOR_TIME - GENESIS_TIME) * height / ANCHOR_HEIGHT
+    } else {
+        ANCHOR_TIME + (height - ANCHOR_HEIGHT) * 600
+    };
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+/// Generate a random chain code for `new`, degenerate output rejected by `check_chain_code` is
+/// drawn again.
+pub fn rand_chaincode<R: Rng + ?Sized>(rng: &mut R) -> [u8; 32] {
     let mut chain_code = [0u8; 32];
-    rng.fill(&mut chain_code);
-    chain_code
+    loop {
+        rng.fill(&mut chain_code);
+        if check_chain_code(&chain_code).is_ok() {
+            return chain_code;
+        }
+    }
 }
 
-pub fn rand_nonce() -> Vec<u8> {
-    let rng = &mut rand::thread_rng();
-    let mut nonce = [0u8; 16];
-    rng.fill(&mut nonce);
-    nonce.to_vec()
+/// Prompt for a CVC on the terminal without echoing it, so the code can't be read off the screen.
+#[cfg(feature = "cli")]
+pub fn read_cvc_secure(prompt: &str) -> std::io::Result<String> {
+    let cvc = rpassword::prompt_password(prompt)?;
+    Ok(cvc.trim().to_string())
+}
+
+/// Decrypt a TAPSIGNER backup with the backup key printed on the card, given as 32 hex digits.
+///
+/// The backup is AES-128-CTR encrypted with a zero IV. The decrypted text has the XPRV on the first
+/// line followed by the derivation path in use, only the XPRV is returned.
+#[cfg(feature = "std")]
+pub fn decrypt_backup(backup: &[u8], backup_key: &str) -> Result<Xpriv, Error> {
+    let key = Vec::<u8>::from_hex(backup_key.trim())
+        .ok()
+        .filter(|key| key.len() == 16)
+        .map(Zeroizing::new)
+        .ok_or_else(|| Error::InvalidBackup("Backup key must be 32 hex digits".to_string()))?;
+
+    let mut data = Zeroizing::new(backup.to_vec());
+    let mut cipher = Aes128Ctr::new(key.as_slice().into(), &[0u8; 16].into());
+    cipher.apply_keystream(data.as_mut_slice());
+
+    let xprv = core::str::from_utf8(&data)
+        .ok()
+        .and_then(|text| text.lines().next())
+        .ok_or_else(|| Error::InvalidBackup("Wrong backup key or corrupted backup".to_string()))?;
+    Xpriv::from_str(xprv.trim())
+        .map_err(|e| Error::InvalidBackup(format!("Wrong backup key or corrupted backup: {}", e)))
+}
+
+/// Generate a random app nonce, the card rejects nonces that are all the same byte.
+pub fn rand_nonce<R: Rng + ?Sized>(rng: &mut R) -> [u8; USER_NONCE_SIZE] {
+    let mut nonce = [0u8; USER_NONCE_SIZE];
+    loop {
+        rng.fill(&mut nonce);
+        if check_nonce(&nonce).is_ok() {
+            return nonce;
+        }
+    }
 }
 
 // Errors
@@ -400,3 +1808,943 @@ pub fn rand_nonce() -> Vec<u8> {
 //         Error::CiborDe(e.to_string())
 //     }
 // }
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    #[cfg(feature = "test-utils")]
+    use crate::mock::{
+        cbor_map, expect_satscard, expect_tapsigner, mock_satscard, mock_tap_signer,
+    };
+    use hex::DisplayHex;
+    use secp256k1::rand;
+
+    /// Returns `uniform` bytes for the first nonce, then counts up.
+    struct UniformThenCountingRng {
+        uniform: usize,
+        count: u8,
+    }
+
+    impl RngCore for UniformThenCountingRng {
+        fn next_u32(&mut self) -> u32 {
+            let mut bytes = [0u8; 4];
+            self.fill_bytes(&mut bytes);
+            u32::from_le_bytes(bytes)
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            let mut bytes = [0u8; 8];
+            self.fill_bytes(&mut bytes);
+            u64::from_le_bytes(bytes)
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            for byte in dest.iter_mut() {
+                if self.uniform > 0 {
+                    self.uniform -= 1;
+                    *byte = 0xaa;
+                } else {
+                    self.count = self.count.wrapping_add(1);
+                    *byte = self.count;
+                }
+            }
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_harden() {
+        assert_eq!(harden(84).unwrap(), HARDENED + 84);
+        assert_eq!(harden(HARDENED - 1).unwrap(), u32::MAX);
+        assert!(matches!(harden(HARDENED), Err(Error::InvalidPath(_))));
+        assert!(matches!(harden(u32::MAX), Err(Error::InvalidPath(_))));
+    }
+
+    #[test]
+    fn test_approx_block_time() {
+        let secs = |height| {
+            approx_block_time(height)
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+        };
+        assert_eq!(secs(0), 1_231_006_505);
+        assert_eq!(secs(800_000), 1_690_168_629);
+        assert_eq!(secs(800_006), 1_690_168_629 + 3600);
+        assert!(secs(400_000) > secs(0) && secs(400_000) < secs(800_000));
+    }
+
+    #[test]
+    fn test_rand_nonce_retries_uniform() {
+        let mut rng = UniformThenCountingRng {
+            uniform: USER_NONCE_SIZE,
+            count: 0,
+        };
+        let nonce = rand_nonce(&mut rng);
+        assert_ne!(nonce, [0xaa; USER_NONCE_SIZE]);
+        assert!(check_nonce(&nonce).is_ok());
+    }
+
+    #[test]
+    fn test_rand_chaincode_retries_uniform() {
+        let mut rng = UniformThenCountingRng {
+            uniform: 32,
+            count: 0,
+        };
+        let chain_code = rand_chaincode(&mut rng);
+        assert_ne!(chain_code, [0xaa; 32]);
+        assert!(check_chain_code(&chain_code).is_ok());
+    }
+
+    #[test]
+    fn test_uniform_nonce_rejected() {
+        let result = CheckCommand::new(vec![0xaa; USER_NONCE_SIZE]);
+        assert!(matches!(result, Err(Error::InvalidNonce(_))));
+        let result = ReadCommand::unauthenticated(vec![1, 2, 3]);
+        assert!(matches!(result, Err(Error::InvalidNonce(_))));
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_change_cvc_length() {
+        let mut card = mock_tap_signer(vec![]);
+        for new_cvc in ["12345", &"1".repeat(33)] {
+            let result = card.change_cvc("123456".to_string(), new_cvc.to_string());
+            assert!(matches!(result, Err(Error::InvalidCvcLength { .. })));
+        }
+        // nothing was sent after the status select
+        assert_eq!(card.transport.sent().len(), 1);
+    }
+
+    #[test]
+    fn test_decrypt_backup() {
+        let xprv = Xpriv::new_master(Network::Bitcoin, &[7u8; 32]).unwrap();
+        let backup_key = "00112233445566778899aabbccddeeff";
+        let mut backup = format!("{}\nm/84h/0h/0h\n", xprv).into_bytes();
+        let key = Vec::<u8>::from_hex(backup_key).unwrap();
+        Aes128Ctr::new(key.as_slice().into(), &[0u8; 16].into()).apply_keystream(&mut backup);
+
+        let decrypted = decrypt_backup(&backup, backup_key).unwrap();
+        assert_eq!(decrypted, xprv);
+
+        let result = decrypt_backup(&backup, "ffeeddccbbaa99887766554433221100");
+        assert!(matches!(result, Err(Error::InvalidBackup(_))));
+        let result = decrypt_backup(&backup, "0011");
+        assert!(matches!(result, Err(Error::InvalidBackup(_))));
+    }
+
+    #[test]
+    fn test_slot() {
+        assert_eq!(Slot::new(0, 10).unwrap().index(), 0);
+        assert_eq!(Slot::new(9, 10).unwrap().index(), 9);
+        assert!(matches!(Slot::new(10, 10), Err(Error::InvalidSlot(_))));
+        assert!(matches!(Slot::new(0, 0), Err(Error::InvalidSlot(_))));
+    }
+
+    fn dump_response(sealed: Option<bool>, used: Option<bool>) -> DumpResponse {
+        DumpResponse {
+            slot: 1,
+            privkey: None,
+            pubkey: Vec::new(),
+            chain_code: None,
+            master_pk: None,
+            tampered: None,
+            used,
+            sealed,
+            addr: None,
+            card_nonce: vec![0; CARD_NONCE_SIZE],
+        }
+    }
+
+    #[test]
+    fn test_slot_state_sealed() {
+        let state = SlotState::from_dump(&dump_response(Some(true), None), None).unwrap();
+        assert_eq!(
+            state,
+            SlotState::Sealed {
+                slot: 1,
+                pubkey: None
+            }
+        );
+    }
+
+    #[test]
+    fn test_slot_state_unused() {
+        let state = SlotState::from_dump(&dump_response(None, Some(false)), None).unwrap();
+        assert_eq!(state, SlotState::Unused { slot: 1 });
+    }
+
+    #[test]
+    fn test_slot_state_unsealed() {
+        let secp = Secp256k1::new();
+        let (privkey, pubkey) = secp.generate_keypair(&mut rand::thread_rng());
+        let session_key = [0x5a; 32];
+        let mut response = dump_response(Some(false), Some(true));
+        response.privkey = Some(xor_session_key(&privkey.secret_bytes(), &session_key));
+        response.pubkey = pubkey.serialize().to_vec();
+        response.addr = Some("bc1qexample".to_string());
+
+        match SlotState::from_dump(&response, Some(&session_key)).unwrap() {
+            SlotState::Unsealed {
+                slot,
+                privkey: decrypted,
+                pubkey: Some(slot_pubkey),
+                addr,
+                tampered,
+                ..
+            } => {
+                assert_eq!(slot, 1);
+                assert_eq!(decrypted, Some(privkey));
+                assert_eq!(slot_pubkey, pubkey);
+                assert_eq!(addr.as_deref(), Some("bc1qexample"));
+                assert!(!tampered);
+            }
+            state => panic!("unexpected state {:?}", state),
+        }
+
+        // without the CVC only the public details are given
+        response.privkey = None;
+        let state = SlotState::from_dump(&response, None).unwrap();
+        assert!(matches!(state, SlotState::Unsealed { privkey: None, .. }));
+    }
+
+    #[test]
+    fn test_slot_state_to_wif() {
+        let mut one = [0u8; 32];
+        one[31] = 1;
+        let session_key = [0x5a; 32];
+        let mut response = dump_response(Some(false), Some(true));
+        response.privkey = Some(xor_session_key(&one, &session_key));
+        let state = SlotState::from_dump(&response, Some(&session_key)).unwrap();
+        assert_eq!(
+            state.to_wif(Network::Bitcoin).as_deref(),
+            Some("KwDiBf89QgGbjEhKnhXJuH7LrciVrZi3qYjgd9M7rFU73sVHnoWn")
+        );
+        assert_eq!(
+            state.to_wif(Network::Testnet).as_deref(),
+            Some("cMahea7zqjxrtgAbB7LSGbcQUr1uX1ojuat9jZodMN87JcbXMTcA")
+        );
+
+        response.privkey = None;
+        let state = SlotState::from_dump(&response, None).unwrap();
+        assert_eq!(state.to_wif(Network::Bitcoin), None);
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_dump_all_slots() {
+        use ciborium::value::Value;
+
+        let mut card = mock_satscard(
+            (0..10u8)
+                .map(|slot| {
+                    cbor_map(vec![
+                        ("slot", slot.into()),
+                        if slot == 0 {
+                            ("sealed", Value::Bool(true))
+                        } else {
+                            ("used", Value::Bool(false))
+                        },
+                        (
+                            "card_nonce",
+                            Value::Bytes(vec![slot + 0x10; CARD_NONCE_SIZE]),
+                        ),
+                    ])
+                })
+                .collect(),
+        );
+
+        let states = card.dump_all_slots(None).unwrap();
+        assert_eq!(states.len(), 10);
+        assert!(matches!(states[0], SlotState::Sealed { slot: 0, .. }));
+        assert_eq!(states[9], SlotState::Unused { slot: 9 });
+        assert_eq!(card.card_nonce, vec![0x19; CARD_NONCE_SIZE]);
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_next_slot() {
+        use ciborium::value::Value;
+
+        let dump_response = |state: (&'static str, bool)| {
+            cbor_map(vec![
+                ("slot", 0.into()),
+                (state.0, Value::Bool(state.1)),
+                ("card_nonce", Value::Bytes(vec![0x42; CARD_NONCE_SIZE])),
+            ])
+        };
+        // an unused active slot is the next one, an unsealed one is followed by the next
+        let mut card = mock_satscard(vec![dump_response(("used", false))]);
+        assert_eq!(card.next_slot().unwrap().index(), 0);
+        let mut card = mock_satscard(vec![dump_response(("used", true))]);
+        assert_eq!(card.next_slot().unwrap().index(), 1);
+
+        let mut card = mock_satscard(vec![dump_response(("sealed", true))]);
+        assert!(matches!(card.next_slot(), Err(Error::InvalidSlot(_))));
+        let mut card = mock_satscard(vec![dump_response(("used", true))]);
+        card.slots = (9, 10);
+        assert!(matches!(card.next_slot(), Err(Error::InvalidSlot(_))));
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_slot_addresses() {
+        use ciborium::value::Value;
+
+        let secp = Secp256k1::new();
+        let pubkeys: Vec<PublicKey> = (0..2)
+            .map(|_| secp.generate_keypair(&mut rand::thread_rng()).1)
+            .collect();
+        let dump_response = |slot: u8| {
+            let mut entries = vec![("slot", slot.into())];
+            match slot {
+                // unsealed without the private key, sealed with its pubkey included
+                0 => entries.push(("used", Value::Bool(true))),
+                1 => entries.push(("sealed", Value::Bool(true))),
+                _ => entries.push(("used", Value::Bool(false))),
+            }
+            if let Some(pubkey) = pubkeys.get(slot as usize) {
+                entries.push(("pubkey", Value::Bytes(pubkey.serialize().to_vec())));
+            }
+            entries.push((
+                "card_nonce",
+                Value::Bytes(vec![slot + 0x10; CARD_NONCE_SIZE]),
+            ));
+            cbor_map(entries)
+        };
+        let mut card = mock_satscard((0..10u8).map(dump_response).collect());
+
+        let addresses = card.slot_addresses("123456".to_string()).unwrap();
+        assert_eq!(addresses.len(), 2);
+        for ((slot, state, address), pubkey) in addresses.iter().zip(&pubkeys) {
+            assert_eq!(slot.index() as usize, state.slot());
+            assert_eq!(
+                *address,
+                Address::p2wpkh(&bitcoin::PublicKey::new(*pubkey), Network::Bitcoin).unwrap()
+            );
+        }
+        assert!(matches!(addresses[0].1, SlotState::Unsealed { .. }));
+        assert!(matches!(addresses[1].1, SlotState::Sealed { .. }));
+    }
+
+    #[test]
+    fn test_recover_id() {
+        let secp = Secp256k1::new();
+        let (privkey, pubkey) = secp.generate_keypair(&mut rand::thread_rng());
+        let msg_hash = signed_msg_hash("hello");
+        let msg = Message::from_digest(msg_hash.to_byte_array());
+        let sig = secp.sign_ecdsa(&msg, &privkey).serialize_compact();
+
+        let id = recover_id(&msg, &sig, &pubkey).unwrap();
+        let signature = RecoverableSignature::from_compact(&sig, id).unwrap();
+        let message_signature = MessageSignature::new(signature, true);
+        let address = Address::p2pkh(&bitcoin::PublicKey::new(pubkey), Network::Bitcoin);
+        assert!(message_signature
+            .is_signed_by_address(&secp, &address, msg_hash)
+            .unwrap());
+
+        let (_, other) = secp.generate_keypair(&mut rand::thread_rng());
+        let result = recover_id(&msg, &sig, &other);
+        assert!(matches!(result, Err(Error::BadSignature(_))));
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_clone_card_state() {
+        use crate::mock::synthetic;
+
+        let transport = FnTransport::from_fn(|_: &[u8]| Ok(synthetic::SATSCARD_STATUS.to_vec()));
+        let mut card = expect_satscard(transport.to_cktap().unwrap());
+        let snapshot = card.clone();
+        card.set_card_nonce(vec![0x42; CARD_NONCE_SIZE]);
+        card.set_auth_delay(Some(5));
+
+        assert_ne!(snapshot.card_nonce, card.card_nonce);
+        assert_eq!(snapshot.auth_delay, None);
+        assert_eq!(snapshot.slots, card.slots);
+    }
+
+    /// `address` of a SATSCARD whose active slot key is `slot_key` and whose status reports
+    /// `partial_addr`.
+    #[cfg(feature = "test-utils")]
+    fn address_with(
+        slot_key: SecretKey,
+        partial_addr: String,
+        network: Option<Network>,
+    ) -> Result<Address, Error> {
+        use crate::mock::{fake_card, status_with, synthetic};
+        use ciborium::value::Value;
+
+        let status = status_with(
+            synthetic::SATSCARD_STATUS,
+            "addr",
+            Value::Text(partial_addr),
+        );
+        let transport = fake_card(&status, move |command, card_nonce| {
+            assert_eq!(command.name(), Some("read"));
+            let mut message = b"OPENDIME".to_vec();
+            message.extend(card_nonce);
+            message.extend(command.bytes("nonce").unwrap());
+            message.push(0);
+            let digest = Message::from_hashed_data::<sha256::Hash>(&message);
+            let sig = Secp256k1::new().sign_ecdsa(&digest, &slot_key);
+            let pubkey = PublicKey::from_secret_key(&Secp256k1::new(), &slot_key);
+            vec![
+                ("sig", Value::Bytes(sig.serialize_compact().to_vec())),
+                ("pubkey", Value::Bytes(pubkey.serialize().to_vec())),
+            ]
+        });
+        let mut sc = expect_satscard(transport.to_cktap().unwrap());
+        if let Some(network) = network {
+            sc = sc.with_network(network);
+        }
+        sc.address()
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_address() {
+        let secp = Secp256k1::new();
+        let (slot_key, slot_pubkey) = secp.generate_keypair(&mut rand::thread_rng());
+        let address = Address::p2wpkh(&bitcoin::PublicKey::new(slot_pubkey), Network::Bitcoin)
+            .unwrap()
+            .to_string();
+        let partial = format!("{}___{}", &address[..8], &address[address.len() - 8..]);
+
+        let mainnet = address_with(slot_key, partial.clone(), None).unwrap();
+        assert_eq!(mainnet.to_string(), address);
+
+        // the override only changes how the address is rendered, it's still checked against the
+        // card's mainnet partial address
+        let testnet = address_with(slot_key, partial.clone(), Some(Network::Testnet)).unwrap();
+        assert_eq!(
+            testnet,
+            Address::p2wpkh(&bitcoin::PublicKey::new(slot_pubkey), Network::Testnet).unwrap()
+        );
+
+        // a card reporting an address for another key is caught
+        let (other_key, _) = secp.generate_keypair(&mut rand::thread_rng());
+        assert!(matches!(
+            address_with(other_key, partial, None),
+            Err(Error::InvalidAddress(_))
+        ));
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_read_slot() {
+        use crate::mock::{fake_card, synthetic};
+        use ciborium::value::Value;
+
+        let (slot_key, slot_pubkey) = Secp256k1::new().generate_keypair(&mut rand::thread_rng());
+        let read_signed_for = |signed_slot: u8, partial: Option<String>| {
+            let transport = fake_card(synthetic::SATSCARD_STATUS, move |command, card_nonce| {
+                assert_eq!(command.name(), Some("read"));
+                let mut message = b"OPENDIME".to_vec();
+                message.extend(card_nonce);
+                message.extend(command.bytes("nonce").unwrap());
+                message.push(signed_slot);
+                let digest = Message::from_hashed_data::<sha256::Hash>(&message);
+                let sig = Secp256k1::new().sign_ecdsa(&digest, &slot_key);
+                vec![
+                    ("sig", Value::Bytes(sig.serialize_compact().to_vec())),
+                    ("pubkey", Value::Bytes(slot_pubkey.serialize().to_vec())),
+                ]
+            });
+            let mut sc = expect_satscard(transport.to_cktap().unwrap());
+            sc.addr = partial;
+            sc.read_slot()
+        };
+
+        let address =
+            Address::p2wpkh(&bitcoin::PublicKey::new(slot_pubkey), Network::Bitcoin).unwrap();
+        let full = address.to_string();
+        let partial = format!("{}___{}", &full[..8], &full[full.len() - 8..]);
+        let result = read_signed_for(0, Some(partial)).unwrap();
+        assert_eq!(result.slot.index(), 0);
+        assert_eq!(result.pubkey, slot_pubkey);
+        assert_eq!(result.address, address);
+        assert!(result.verified);
+        // nothing to check an unsealed slot's address against
+        assert!(!read_signed_for(0, None).unwrap().verified);
+        assert!(
+            !read_signed_for(0, Some("bc1qxxxxx___xxxxxxxx".to_string()))
+                .unwrap()
+                .verified
+        );
+        // signed for another slot than the active one
+        assert!(read_signed_for(1, None).is_err());
+    }
+
+    #[test]
+    fn test_check_no_taproot_inputs() {
+        use bitcoin::absolute::LockTime;
+        use bitcoin::transaction::Version;
+        use bitcoin::{Amount, OutPoint, ScriptBuf, Transaction, TxIn, TxOut};
+
+        let secp = Secp256k1::new();
+        let (_, pubkey) = secp.generate_keypair(&mut rand::thread_rng());
+        let card_path = DerivationPath::from_str("m/84'/0'/0'").unwrap();
+        let fingerprint = Fingerprint::from([1, 2, 3, 4]);
+        let origin = (
+            fingerprint,
+            DerivationPath::from_str("m/84'/0'/0'/0/1").unwrap(),
+        );
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                ..Default::default()
+            }],
+            output: vec![],
+        };
+        let psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        let check = |psbt: &Psbt| check_no_taproot_inputs(psbt, fingerprint, &card_path);
+
+        let mut segwit = psbt.clone();
+        segwit.inputs[0].witness_utxo = Some(TxOut {
+            value: Amount::from_sat(1000),
+            script_pubkey: ScriptBuf::new_p2wpkh(
+                &bitcoin::PublicKey::new(pubkey).wpubkey_hash().unwrap(),
+            ),
+        });
+        segwit.inputs[0]
+            .bip32_derivation
+            .insert(pubkey, origin.clone());
+        assert!(check(&segwit).is_ok());
+
+        let mut p2tr = segwit.clone();
+        let (xonly, _) = pubkey.x_only_public_key();
+        p2tr.inputs[0].witness_utxo = Some(TxOut {
+            value: Amount::from_sat(1000),
+            script_pubkey: ScriptBuf::new_p2tr(&secp, xonly, None),
+        });
+        assert!(matches!(check(&p2tr), Err(Error::UnsupportedInputType(_))));
+
+        let mut tap_origin = psbt.clone();
+        tap_origin.inputs[0]
+            .tap_key_origins
+            .insert(xonly, (vec![], origin));
+        assert!(matches!(
+            check(&tap_origin),
+            Err(Error::UnsupportedInputType(_))
+        ));
+
+        // taproot inputs for other keys are left alone
+        let mut other = tap_origin.clone();
+        other.inputs[0].tap_key_origins.insert(
+            xonly,
+            (vec![], (Fingerprint::from([9; 4]), card_path.clone())),
+        );
+        assert!(check(&other).is_ok());
+    }
+
+    #[test]
+    fn test_card_subpath() {
+        let card_path = DerivationPath::from_str("m/84'/0'/0'").unwrap();
+        let subpath =
+            |path: &str| card_subpath(&card_path, &DerivationPath::from_str(path).unwrap());
+        assert_eq!(subpath("m/84'/0'/0'"), Some(vec![]));
+        assert_eq!(subpath("m/84'/0'/0'/1/5"), Some(vec![1, 5]));
+        assert_eq!(subpath("m/84'/0'/0'/1/5/3"), None);
+        assert_eq!(subpath("m/84'/0'/0'/1'"), None);
+        assert_eq!(subpath("m/84'/0'/1'/0/0"), None);
+    }
+
+    /// Sign response for `digest` signed by `signer`, reporting `pubkey`.
+    #[cfg(feature = "test-utils")]
+    fn sign_response(signer: &SecretKey, pubkey: &PublicKey, digest: [u8; 32]) -> Vec<u8> {
+        use ciborium::value::Value;
+
+        let sig = Secp256k1::new().sign_ecdsa(&Message::from_digest(digest), signer);
+        cbor_map(vec![
+            ("slot", 0.into()),
+            ("sig", Value::Bytes(sig.serialize_compact().to_vec())),
+            ("pubkey", Value::Bytes(pubkey.serialize().to_vec())),
+            ("card_nonce", Value::Bytes(vec![0x42; CARD_NONCE_SIZE])),
+        ])
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_tap_signer_address() {
+        use ciborium::value::Value;
+
+        let secp = Secp256k1::new();
+        let xprv = Xpriv::new_master(Network::Bitcoin, &[7; 32]).unwrap();
+        let xpub = Xpub::from_priv(&secp, &xprv);
+        let xpub_response = cbor_map(vec![
+            ("xpub", Value::Bytes(xpub.encode().to_vec())),
+            ("card_nonce", Value::Bytes(vec![0x42; 16])),
+        ]);
+        let mut card = mock_tap_signer(vec![xpub_response]);
+        let address = card.address(5, "123456".to_string()).unwrap();
+
+        let path = DerivationPath::from_str("m/0/5").unwrap();
+        let pubkey = xpub.derive_pub(&secp, &path).unwrap().public_key;
+        let expected =
+            Address::p2wpkh(&bitcoin::PublicKey::new(pubkey), card.render_network()).unwrap();
+        assert_eq!(address, expected);
+
+        // hardened indexes are rejected before anything is sent
+        let result = card.address(HARDENED, "123456".to_string());
+        assert!(matches!(result, Err(Error::InvalidPath(_))));
+        assert_eq!(card.transport.sent().len(), 2);
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_sign_unlucky_retry() {
+        use ciborium::value::Value;
+
+        let unlucky = cbor_map(vec![
+            ("error", Value::Text("unlucky number".to_string())),
+            ("code", 205.into()),
+        ]);
+        let (key, pubkey) = Secp256k1::new().generate_keypair(&mut rand::thread_rng());
+        let signed = sign_response(&key, &pubkey, [1; 32]);
+
+        let mut card = mock_tap_signer(vec![unlucky.clone(), unlucky.clone(), signed]);
+        let response = card.sign(vec![1; 32], None, "123456".to_string()).unwrap();
+        assert_eq!(response.pubkey().unwrap(), pubkey);
+        let sent = card.transport.sent();
+        assert_eq!(sent.len(), 4);
+        // each attempt is a different command, not a resend of the rejected bytes
+        assert_ne!(sent[1], sent[2]);
+        assert_ne!(sent[2], sent[3]);
+
+        let mut card = mock_tap_signer(vec![unlucky; SIGN_ATTEMPTS]);
+        assert!(matches!(
+            card.sign(vec![1; 32], None, "123456".to_string()),
+            Err(Error::SignRetryExhausted {
+                attempts: SIGN_ATTEMPTS
+            })
+        ));
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_with_rng() {
+        use secp256k1::rand::rngs::mock::StepRng;
+
+        let xpub_command = || {
+            let mut card = mock_tap_signer(vec![]).with_rng(StepRng::new(1, 1));
+            card.build_command_bytes("123456".to_string(), |epubkey, xcvc| {
+                Ok(XpubCommand::new(false, epubkey, xcvc))
+            })
+            .unwrap()
+        };
+        assert_eq!(xpub_command(), xpub_command());
+
+        let card = mock_tap_signer(vec![]).with_rng(StepRng::new(1, 1));
+        assert_eq!(
+            card.app_nonce(),
+            rand_nonce(&mut StepRng::new(1, 1)).to_vec()
+        );
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_sign_subpath() {
+        let mut card = mock_tap_signer(vec![]);
+        let sent = card.transport.sent().len();
+        for subpath in [vec![0, 1, 2], vec![0, HARDENED]] {
+            assert!(matches!(
+                card.sign(vec![1; 32], Some(subpath), "123456".to_string()),
+                Err(Error::InvalidSubpath(_))
+            ));
+        }
+        assert_eq!(card.transport.sent().len(), sent);
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_sign_checks_response() {
+        use ciborium::value::Value;
+
+        let secp = Secp256k1::new();
+        let account = Xpriv::new_master(Network::Bitcoin, &[7u8; 32]).unwrap();
+        let child = account
+            .derive_priv(&secp, &[ChildNumber::Normal { index: 5 }])
+            .unwrap();
+        let child_pubkey = child.private_key.public_key(&secp);
+        let (other_key, other_pubkey) = secp.generate_keypair(&mut rand::thread_rng());
+        let digest = [9; 32];
+        let xpub_response = cbor_map(vec![
+            (
+                "xpub",
+                Value::Bytes(Xpub::from_priv(&secp, &account).encode().to_vec()),
+            ),
+            ("card_nonce", Value::Bytes(vec![0x41; CARD_NONCE_SIZE])),
+        ]);
+        let sign = |card: &mut TapSigner<_>| {
+            card.sign(digest.to_vec(), Some(vec![5]), "123456".to_string())
+        };
+
+        // the reported pubkey didn't make the signature
+        let mut card = mock_tap_signer(vec![sign_response(
+            &child.private_key,
+            &other_pubkey,
+            digest,
+        )]);
+        assert!(matches!(sign(&mut card), Err(Error::BadSignature(_))));
+
+        // valid signature, but not by the key at the requested subpath
+        let mut card = mock_tap_signer(vec![
+            xpub_response.clone(),
+            sign_response(&other_key, &other_pubkey, digest),
+        ]);
+        card.xpub(false, "123456".to_string()).unwrap();
+        assert!(matches!(sign(&mut card), Err(Error::DerivationMismatch(_))));
+
+        let mut card = mock_tap_signer(vec![
+            xpub_response,
+            sign_response(&child.private_key, &child_pubkey, digest),
+        ]);
+        card.xpub(false, "123456".to_string()).unwrap();
+        assert_eq!(sign(&mut card).unwrap().pubkey().unwrap(), child_pubkey);
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_master_fingerprint_cached() {
+        use ciborium::value::Value;
+
+        let xpub = Xpub::from_priv(
+            &Secp256k1::new(),
+            &Xpriv::new_master(Network::Bitcoin, &[7u8; 32]).unwrap(),
+        );
+        let mut card = mock_tap_signer(vec![cbor_map(vec![
+            ("xpub", Value::Bytes(xpub.encode().to_vec())),
+            ("card_nonce", Value::Bytes(vec![0x42; CARD_NONCE_SIZE])),
+        ])]);
+
+        let fingerprint = card.master_fingerprint("123456".to_string()).unwrap();
+        assert_eq!(fingerprint, xpub.fingerprint());
+        // the second call is answered from the cache, no response is queued for it
+        assert_eq!(
+            card.master_fingerprint("123456".to_string()).unwrap(),
+            fingerprint
+        );
+        assert_eq!(card.transport.sent().len(), 2);
+    }
+
+    #[test]
+    fn test_descriptor_checksum() {
+        // BIP-380 test vector
+        assert_eq!(descriptor_checksum("raw(deadbeef)").unwrap(), "89f8spxm");
+        assert!(descriptor_checksum("wpkh(\u{e9})").is_err());
+    }
+
+    #[test]
+    fn test_verify_slot_signature() {
+        let secp = Secp256k1::new();
+        let (slot_key, slot_pubkey) = secp.generate_keypair(&mut rand::thread_rng());
+        let address =
+            Address::p2wpkh(&bitcoin::PublicKey::new(slot_pubkey), Network::Bitcoin).unwrap();
+        let suffix = &address.to_string()[address.to_string().len() - 8..];
+        let fragment = format!("u=S&o=0&r={}&n=7664168a4ef7b8e8&s=", suffix);
+        let digest = Message::from_hashed_data::<sha256::Hash>(fragment.as_bytes());
+        let sig = secp.sign_ecdsa(&digest, &slot_key).serialize_compact();
+        let url = format!(
+            "getsatscard.com/start#{}{}",
+            fragment,
+            sig.to_lower_hex_string()
+        );
+        let url = NfcUrl::parse(&url).unwrap();
+        assert_eq!(verify_slot_signature(&url, &address).unwrap(), slot_pubkey);
+
+        // signature from another key
+        let (_, other_pubkey) = secp.generate_keypair(&mut rand::thread_rng());
+        let other =
+            Address::p2wpkh(&bitcoin::PublicKey::new(other_pubkey), Network::Bitcoin).unwrap();
+        let mut forged = url.clone();
+        forged.params[2].1 = other.to_string()[other.to_string().len() - 8..].to_string();
+        assert!(matches!(
+            verify_slot_signature(&forged, &other),
+            Err(Error::BadSignature(_))
+        ));
+
+        // tampered nonce
+        let mut forged = url.clone();
+        forged.params[3].1 = "0000000000000000".to_string();
+        assert!(verify_slot_signature(&forged, &address).is_err());
+
+        // url for another address
+        assert!(matches!(
+            verify_slot_signature(&url, &other),
+            Err(Error::InvalidAddress(_))
+        ));
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_init() {
+        use crate::mock::{status_with, synthetic, MockTransport};
+        use ciborium::value::Value;
+
+        let status = |card_nonce: u8| {
+            status_with(
+                synthetic::TAPSIGNER_STATUS,
+                "card_nonce",
+                Value::Bytes(vec![card_nonce; 16]),
+            )
+        };
+        // an uninitialized card has no path
+        let transport = MockTransport::new(vec![status_with(&status(1), "path", Value::Null)]);
+        transport.push_response(cbor_map(vec![
+            ("slot", Value::Integer(0.into())),
+            ("card_nonce", Value::Bytes(vec![2; 16])),
+        ]));
+        // a status echoing the nonce of the `new` response is taken like any other status
+        transport.push_response(status(2));
+        let mut card = expect_tapsigner(transport.to_cktap().unwrap());
+        assert!(card.path.is_none());
+
+        // weak chain codes are rejected before anything is sent
+        for chain_code in [vec![7; 16], vec![0; 32]] {
+            let result = card.init(chain_code, "123456".to_string());
+            assert!(matches!(result, Err(Error::InvalidChainCode(_))));
+        }
+        assert_eq!(card.transport.sent().len(), 1);
+
+        let chain_code: Vec<u8> = (0..32).collect();
+        let result = card.init(chain_code.clone(), "123456".to_string()).unwrap();
+        assert_eq!(result.path.to_string(), "m/84'/0'/0'");
+        assert_eq!(result.pubkey, card.pubkey);
+        assert_eq!(result.card_nonce, vec![2; 16]);
+        assert!(card.path.is_some());
+
+        // only once per card
+        let result = card.init(chain_code, "123456".to_string());
+        assert!(matches!(result, Err(Error::AlreadyInitialized)));
+        assert_eq!(card.transport.sent().len(), 3);
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_lifecycle() {
+        use crate::mock::{synthetic, MockTransport};
+
+        let mut card = MockTransport::new(vec![synthetic::TAPSIGNER_STATUS.to_vec()])
+            .to_cktap()
+            .unwrap();
+        assert_eq!(card.lifecycle(), CardLifecycle::Active);
+        card.tap_signer_mut().unwrap().path = None;
+        assert_eq!(card.lifecycle(), CardLifecycle::Uninitialized);
+
+        let card = MockTransport::new(vec![synthetic::SATSCARD_STATUS.to_vec()])
+            .to_cktap()
+            .unwrap();
+        assert_eq!(card.lifecycle(), CardLifecycle::Active);
+        if let CkTapCard::SatsCard(mut sc) = card {
+            sc.addr = None;
+            assert_eq!(sc.lifecycle(), CardLifecycle::Unsealed);
+            sc.slots = (9, 10);
+            assert_eq!(sc.lifecycle(), CardLifecycle::Used);
+        }
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_from_transport() {
+        use crate::mock::{synthetic, MockTransport};
+
+        let transport = MockTransport::new(vec![synthetic::SATSCARD_STATUS.to_vec()]);
+        let card = CkTapCard::from_transport(transport).unwrap();
+        assert!(matches!(card, CkTapCard::SatsCard(_)));
+        // applet select only, the status comes back in its response
+        if let CkTapCard::SatsCard(sc) = card {
+            assert_eq!(
+                sc.transport.sent(),
+                vec![AppletSelect::default().apdu_bytes().unwrap()]
+            );
+        }
+
+        let transport = MockTransport::new(vec![synthetic::TAPSIGNER_STATUS.to_vec()]);
+        let card = CkTapCard::from_transport(transport).unwrap();
+        assert!(matches!(card, CkTapCard::TapSigner(_)));
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_card_id() {
+        use crate::mock::{synthetic, MockTransport};
+        use std::collections::HashSet;
+
+        let tap = |fixture: &[u8]| {
+            MockTransport::new(vec![fixture.to_vec()])
+                .to_cktap()
+                .unwrap()
+        };
+        let mut seen = HashSet::new();
+        assert!(seen.insert(tap(synthetic::TAPSIGNER_STATUS).id()));
+        // the same card tapped again has a new nonce but the same identity
+        assert!(!seen.insert(tap(synthetic::TAPSIGNER_STATUS).id()));
+        let card = tap(synthetic::SATSCARD_STATUS);
+        assert_eq!(card.id().pubkey(), &card.snapshot().pubkey);
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_card_display() {
+        use crate::mock::{synthetic, MockTransport};
+
+        let card = MockTransport::new(vec![synthetic::TAPSIGNER_STATUS.to_vec()])
+            .to_cktap()
+            .unwrap();
+        assert_eq!(
+            card.to_string(),
+            "TAPSIGNER v1.0.3, mainnet, path m/84'/0'/0', 1 backup"
+        );
+
+        let card = MockTransport::new(vec![synthetic::SATSCARD_STATUS.to_vec()])
+            .to_cktap()
+            .unwrap();
+        assert_eq!(card.to_string(), "SATSCARD v1.0.3, mainnet, slot 0 of 10");
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn test_card_snapshot() {
+        use crate::mock::{synthetic, MockTransport};
+
+        let transport = MockTransport::new(vec![synthetic::TAPSIGNER_STATUS.to_vec()]);
+        let card = transport.to_cktap().unwrap();
+        let snapshot = card.snapshot();
+        assert_eq!(snapshot.kind, CardKind::TapSigner);
+        assert_eq!(snapshot.slots, None);
+
+        let mut cbor = Vec::new();
+        ciborium::ser::into_writer(&snapshot, &mut cbor).unwrap();
+        let cached: CardSnapshot = ciborium::de::from_reader(&cbor[..]).unwrap();
+        assert_eq!(cached, snapshot);
+        if let CkTapCard::TapSigner(ts) = &card {
+            assert_eq!(cached.pubkey, ts.pubkey);
+            assert_eq!(
+                cached.derivation_path().unwrap(),
+                ts.derivation_path().unwrap()
+            );
+        }
+
+        let transport = MockTransport::new(vec![synthetic::SATSCARD_STATUS.to_vec()]);
+        let snapshot = transport.to_cktap().unwrap().snapshot();
+        assert_eq!(snapshot.kind, CardKind::SatsCard);
+        assert!(snapshot.slots.is_some() && snapshot.path.is_none());
+    }
+
+    #[test]
+    fn test_matches_partial_address() {
+        let address = "bc1qsqkhvjnkaa8e3y4zrgpp0gm4tlhv3jqf735wvl";
+        assert!(matches_partial_address(address, "bc1qsqkhv___qf735wvl"));
+        assert!(matches_partial_address(address, "bc1qsqkhv..qf735wvl"));
+        assert!(matches_partial_address(address, address));
+        assert!(!matches_partial_address(address, "bc1qsqkhv___qf735wvx"));
+        assert!(!matches_partial_address(address, "tb1qsqkhv___qf735wvl"));
+    }
+}
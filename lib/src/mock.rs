@@ -0,0 +1,266 @@
+//! Mock transport for testing card flows without a card or reader.
+
+use crate::apdu::{Error, CARD_NONCE_SIZE};
+use crate::commands::CkTransport;
+use crate::{CkTapCard, SatsCard, TapSigner};
+use ciborium::value::Value;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+
+/// Synthetic CBOR responses, written by hand following the field list of the protocol docs and
+/// not captured from real cards. The card pubkey is the secp256k1 generator point and the other
+/// values are made up, eg. the SATSCARD `addr` is not derived from any key. A card may send
+/// fields these lack or encode them differently, so passing tests against them do not show a
+/// real card is handled.
+pub mod synthetic {
+    /// SATSCARD status response, slot 0 of 10 active.
+    pub const SATSCARD_STATUS: &[u8] = include_bytes!("../tests/synthetic/satscard_status.cbor");
+    /// TAPSIGNER status response, initialized at m/84h/0h/0h with one backup.
+    pub const TAPSIGNER_STATUS: &[u8] = include_bytes!("../tests/synthetic/tapsigner_status.cbor");
+    /// Card error response, 401 bad auth.
+    pub const BAD_AUTH_ERROR: &[u8] = include_bytes!("../tests/synthetic/bad_auth_error.cbor");
+}
+
+/// The SATSCARD from `to_cktap`, panics for any other card.
+pub fn expect_satscard<T: CkTransport>(card: CkTapCard<T>) -> SatsCard<T> {
+    match card {
+        CkTapCard::SatsCard(sc) => sc,
+        card => panic!("expected a SATSCARD, got {:?}", card),
+    }
+}
+
+/// The TAPSIGNER from `to_cktap`, panics for any other card.
+pub fn expect_tapsigner<T: CkTransport>(card: CkTapCard<T>) -> TapSigner<T> {
+    match card {
+        CkTapCard::TapSigner(ts) => ts,
+        card => panic!("expected a TAPSIGNER, got {:?}", card),
+    }
+}
+
+/// A SATSCARD answering with the synthetic status and then `responses` in order.
+pub fn mock_satscard(responses: Vec<Vec<u8>>) -> SatsCard<MockTransport> {
+    expect_satscard(mock_card(synthetic::SATSCARD_STATUS, responses))
+}
+
+/// A TAPSIGNER answering with the synthetic status and then `responses` in order.
+pub fn mock_tap_signer(responses: Vec<Vec<u8>>) -> TapSigner<MockTransport> {
+    expect_tapsigner(mock_card(synthetic::TAPSIGNER_STATUS, responses))
+}
+
+fn mock_card(status: &[u8], responses: Vec<Vec<u8>>) -> CkTapCard<MockTransport> {
+    let transport = MockTransport::new(vec![status.to_vec()]);
+    responses
+        .into_iter()
+        .for_each(|response| transport.push_response(response));
+    transport.to_cktap().expect("mock card")
+}
+
+/// A CBOR map response with text keys, eg. `cbor_map(vec![("success", Value::Bool(true))])`.
+pub fn cbor_map(entries: Vec<(&str, Value)>) -> Vec<u8> {
+    cbor(&Value::Map(
+        entries
+            .into_iter()
+            .map(|(key, value)| (Value::Text(key.to_string()), value))
+            .collect(),
+    ))
+}
+
+/// The CBOR `status` response with `field` set to `new_value`, added if missing.
+pub fn status_with(status: &[u8], field: &str, new_value: Value) -> Vec<u8> {
+    let mut status: Value = ciborium::de::from_reader(status).expect("status is CBOR");
+    if let Value::Map(entries) = &mut status {
+        let key = Value::Text(field.to_string());
+        match entries.iter_mut().find(|(k, _)| k == &key) {
+            Some((_, value)) => *value = new_value,
+            None => entries.push((key, new_value)),
+        }
+    }
+    cbor(&status)
+}
+
+/// A command received by a `fake_card`.
+pub struct FakeCommand(Value);
+
+impl FakeCommand {
+    /// The `cmd` field, eg. `"read"`.
+    pub fn name(&self) -> Option<&str> {
+        self.field("cmd").and_then(|value| value.as_text())
+    }
+
+    pub fn field(&self, name: &str) -> Option<&Value> {
+        self.0.as_map().and_then(|entries| {
+            entries
+                .iter()
+                .find(|(key, _)| key.as_text() == Some(name))
+                .map(|(_, value)| value)
+        })
+    }
+
+    /// A byte string field, eg. the app `nonce`.
+    pub fn bytes(&self, name: &str) -> Option<Vec<u8>> {
+        self.field(name)
+            .and_then(|value| value.as_bytes())
+            .map(|bytes| bytes.to_vec())
+    }
+}
+
+/// Transport for a fake card whose responses are computed from the commands, eg. to sign the app
+/// nonce with a known key. The applet select and `status` are answered with `status`, any other
+/// command with the map `respond` returns for it. `respond` gets the card nonce the command was
+/// sent with, every response but the `certs` one carries a new `card_nonce` in place of the one
+/// `status` or `respond` gave.
+pub fn fake_card<F>(status: &[u8], respond: F) -> MockTransport
+where
+    F: FnMut(&FakeCommand, &[u8]) -> Vec<(&'static str, Value)> + 'static,
+{
+    let status: Value = ciborium::de::from_reader(status).expect("status is CBOR");
+    let status: Vec<(Value, Value)> = status.into_map().expect("status is a map");
+    let card_nonce = status
+        .iter()
+        .find(|(key, _)| key.as_text() == Some("card_nonce"))
+        .and_then(|(_, value)| value.as_bytes())
+        .cloned()
+        .unwrap_or_else(|| vec![0; CARD_NONCE_SIZE]);
+    let card_nonce = RefCell::new(card_nonce);
+    let respond = RefCell::new(respond);
+
+    MockTransport::from_fn(move |command_apdu| {
+        // the applet select carries the applet id, not CBOR
+        let command = ciborium::de::from_reader(&command_apdu[5..])
+            .map(FakeCommand)
+            .ok();
+        let (response, new_nonce): (Vec<(Value, Value)>, bool) = match command {
+            Some(command) if command.name() != Some("status") => {
+                let response = (respond.borrow_mut())(&command, &card_nonce.borrow())
+                    .into_iter()
+                    .map(|(key, value)| (Value::Text(key.to_string()), value))
+                    .collect();
+                (response, command.name() != Some("certs"))
+            }
+            _ => (status.clone(), true),
+        };
+        if !new_nonce {
+            return cbor(&Value::Map(response));
+        }
+
+        let next_nonce: Vec<u8> = card_nonce
+            .borrow()
+            .iter()
+            .map(|b| b.wrapping_add(1))
+            .collect();
+        let response = response
+            .into_iter()
+            .filter(|(key, _)| key.as_text() != Some("card_nonce"))
+            .chain([(
+                Value::Text("card_nonce".to_string()),
+                Value::Bytes(next_nonce.clone()),
+            )])
+            .collect();
+        *card_nonce.borrow_mut() = next_nonce;
+        cbor(&Value::Map(response))
+    })
+}
+
+fn cbor(value: &Value) -> Vec<u8> {
+    let mut cbor = Vec::new();
+    ciborium::ser::into_writer(value, &mut cbor).expect("CBOR encoding");
+    cbor
+}
+
+type Handler = Box<dyn Fn(&[u8]) -> Vec<u8>>;
+
+/// Transport that answers command APDUs with scripted responses, either from a queue of canned
+/// response APDUs or by calling a handler with each command APDU.
+pub struct MockTransport {
+    responses: RefCell<VecDeque<Vec<u8>>>,
+    handler: Option<Handler>,
+    sent: RefCell<Vec<Vec<u8>>>,
+}
+
+impl MockTransport {
+    /// Respond to each command with the next response in order.
+    pub fn new(responses: Vec<Vec<u8>>) -> Self {
+        MockTransport {
+            responses: RefCell::new(responses.into()),
+            handler: None,
+            sent: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Respond to each command with the result of the handler.
+    pub fn from_fn<F>(handler: F) -> Self
+    where
+        F: Fn(&[u8]) -> Vec<u8> + 'static,
+    {
+        MockTransport {
+            responses: RefCell::new(VecDeque::new()),
+            handler: Some(Box::new(handler)),
+            sent: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Add a response to the end of the queue.
+    pub fn push_response(&self, response: Vec<u8>) {
+        self.responses.borrow_mut().push_back(response);
+    }
+
+    /// Command APDUs sent so far.
+    pub fn sent(&self) -> Vec<Vec<u8>> {
+        self.sent.borrow().clone()
+    }
+}
+
+impl CkTransport for MockTransport {
+    fn transmit_apdu(&self, command_apdu: Vec<u8>) -> Result<Vec<u8>, Error> {
+        let response = match &self.handler {
+            Some(handler) => handler(&command_apdu),
+            None => self
+                .responses
+                .borrow_mut()
+                .pop_front()
+                .ok_or_else(|| Error::Mock("No more responses queued".to_string()))?,
+        };
+        self.sent.borrow_mut().push(command_apdu);
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apdu::{AppletSelect, CommandApdu};
+
+    #[test]
+    fn test_satscard_status() {
+        let transport = MockTransport::new(vec![synthetic::SATSCARD_STATUS.to_vec()]);
+        let card = transport.to_cktap().unwrap();
+        assert_eq!(card.network(), bitcoin::Network::Bitcoin);
+        let sc = expect_satscard(card);
+        assert_eq!(sc.slots, (0, 10));
+        assert_eq!(
+            sc.transport.sent(),
+            vec![AppletSelect::default().apdu_bytes().unwrap()]
+        );
+    }
+
+    #[test]
+    fn test_tapsigner_status() {
+        let transport = MockTransport::from_fn(|_| synthetic::TAPSIGNER_STATUS.to_vec());
+        let ts = expect_tapsigner(transport.to_cktap().unwrap());
+        assert_eq!(ts.num_backups, Some(1));
+        assert_eq!(ts.backups_taken(), Some(1));
+    }
+
+    #[test]
+    fn test_error_response() {
+        let transport = MockTransport::new(vec![synthetic::BAD_AUTH_ERROR.to_vec()]);
+        let result = transport.to_cktap();
+        assert!(matches!(result, Err(Error::CkTap { code: 401, .. })));
+    }
+
+    #[test]
+    fn test_no_more_responses() {
+        let transport = MockTransport::new(vec![]);
+        assert!(matches!(transport.to_cktap(), Err(Error::Mock(_))));
+    }
+}
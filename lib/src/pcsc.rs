@@ -1,34 +1,280 @@
 extern crate core;
 
+use crate::apdu::{strip_status_word, AppletSelect, CommandApdu, SELECT_CLA_INS_P1P2};
 use crate::Error;
 use crate::{CkTapCard, CkTransport};
-use pcsc::{Card, Context, Protocols, Scope, ShareMode, MAX_BUFFER_SIZE};
+use ciborium::value::Value;
+use pcsc::{Card, Context, Protocols, ReaderState, Scope, ShareMode, State, MAX_BUFFER_SIZE};
+use std::ffi::CString;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
-pub fn find_first() -> Result<CkTapCard<Card>, Error> {
+pub use pcsc::Disposition;
+
+/// Default time to wait for a card response.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Default number of reconnects after a transient reader error.
+pub const DEFAULT_RETRIES: usize = 3;
+
+/// Wait before the first reconnect, doubled for each following one.
+const RETRY_BACKOFF: Duration = Duration::from_millis(50);
+
+/// Commands that only read card state and don't check the card nonce, so they give the same
+/// result when replayed after the reader reset the card.
+const REPLAYABLE_COMMANDS: [&str; 3] = ["status", "certs", "nfc"];
+
+/// Connect to the card on the first available reader.
+pub fn find_first() -> Result<CkTapCard<PcscTransport>, Error> {
+    // Use the first reader.
+    let reader = match list_readers()?.into_iter().next() {
+        Some(reader) => Ok(reader),
+        None => Err(Error::PcSc("No readers are connected.".to_string())),
+    }?;
+
+    connect(&reader)
+}
+
+/// List the names of the connected readers.
+pub fn list_readers() -> Result<Vec<String>, Error> {
     // Establish a PC/SC context.
     let ctx = Context::establish(Scope::User)?;
 
     // List available readers.
     let mut readers_buf = [0; 2048];
-    let mut readers = ctx.list_readers(&mut readers_buf)?;
+    let readers = ctx.list_readers(&mut readers_buf)?;
+    Ok(readers
+        .map(|reader| reader.to_string_lossy().into_owned())
+        .collect())
+}
 
-    // Use the first reader.
-    let reader = match readers.next() {
-        Some(reader) => Ok(reader),
-        None => {
-            //println!("No readers are connected.");
-            Err(Error::PcSc("No readers are connected.".to_string()))
+/// Connect to the card on the reader with the given name.
+pub fn connect(reader_name: &str) -> Result<CkTapCard<PcscTransport>, Error> {
+    let reader = CString::new(reader_name).map_err(|e| Error::PcSc(e.to_string()))?;
+
+    // Establish a PC/SC context.
+    let ctx = Context::establish(Scope::User)?;
+
+    let card = ctx.connect(&reader, ShareMode::Shared, Protocols::ANY)?;
+    let mut transport = PcscTransport::new(card);
+    transport.reader = Some(Reader {
+        context: ctx,
+        name: reader,
+    });
+    transport.to_cktap()
+}
+
+/// PC/SC card transport. Clones share the same card connection.
+///
+/// Flaky readers can reset the card (`SCARD_W_RESET_CARD`) or drop a transaction
+/// (`SCARD_E_NOT_TRANSACTED`). The card is then reconnected with backoff, up to `retries` times,
+/// and the applet selected again. Only the applet select and the read-only `status`, `certs` and
+/// `nfc` commands are replayed, the reset changes the card nonce so any other command fails with
+/// `Error::PcSc` and must be rebuilt by the caller after a new status.
+#[derive(Clone)]
+pub struct PcscTransport {
+    card: Arc<Mutex<Connection>>,
+    reader: Option<Reader>,
+    timeout: Duration,
+    retries: usize,
+}
+
+/// The reader the card was connected on, to connect to the next card tapped on it.
+#[derive(Clone)]
+struct Reader {
+    context: Context,
+    name: CString,
+}
+
+/// The card handle, `None` once disconnected.
+struct Connection(Option<Card>);
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        if let Some(card) = self.0.take() {
+            let _ = card.disconnect(Disposition::ResetCard);
         }
-    }?;
+    }
+}
+
+impl PcscTransport {
+    pub fn new(card: Card) -> Self {
+        PcscTransport {
+            card: Arc::new(Mutex::new(Connection(Some(card)))),
+            reader: None,
+            timeout: DEFAULT_TIMEOUT,
+            retries: DEFAULT_RETRIES,
+        }
+    }
+
+    /// Set how many times to reconnect after a transient reader error, 0 to fail right away.
+    pub fn set_retries(&mut self, retries: usize) {
+        self.retries = retries;
+    }
+
+    pub fn retries(&self) -> usize {
+        self.retries
+    }
+
+    /// Set how long to wait for the card to respond to a command, eg. if the card is removed
+    /// mid-transaction.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Release the card handle, shared by all clones, while keeping the transport. `disposition`
+    /// picks what happens to the card: `LeaveCard` keeps its state, `ResetCard` and `UnpowerCard`
+    /// end the session. Later commands fail until a card is connected again. Dropping the last
+    /// clone disconnects with `ResetCard`.
+    pub fn disconnect(&self, disposition: Disposition) -> Result<(), Error> {
+        let mut connection = self.card.lock().map_err(|e| Error::PcSc(e.to_string()))?;
+        match connection.0.take() {
+            Some(card) => card.disconnect(disposition).map_err(|(_, e)| e.into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Block until a card is on the reader, then connect to it and select the applet, eg. for a
+    /// kiosk serving a stream of tapped cards with one open reader. Returns right away if a card
+    /// is already present, and `Error::Timeout` if none is tapped in time. The transport must
+    /// come from `connect` or `find_first` so the reader is known.
+    pub fn wait_for_card(&self, timeout: Duration) -> Result<CkTapCard<PcscTransport>, Error> {
+        let reader = self
+            .reader
+            .as_ref()
+            .ok_or_else(|| Error::PcSc("Reader is unknown, use pcsc::connect".to_string()))?;
+        let deadline = Instant::now() + timeout;
+        let mut states = [ReaderState::new(reader.name.clone(), State::UNAWARE)];
+        while !states[0].event_state().contains(State::PRESENT) {
+            states[0].sync_current_state();
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match reader.context.get_status_change(remaining, &mut states) {
+                Ok(()) => {}
+                Err(pcsc::Error::Timeout) => {
+                    return Err(Error::Timeout(format!(
+                        "No card tapped within {:?}",
+                        timeout
+                    )))
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let card = reader
+            .context
+            .connect(&reader.name, ShareMode::Shared, Protocols::ANY)?;
+        // replaces the handle of the previous card for all clones
+        self.card.lock().map_err(|e| Error::PcSc(e.to_string()))?.0 = Some(card);
+        self.clone().to_cktap()
+    }
+}
+
+impl PcscTransport {
+    fn transmit_once(&self, apdu: Vec<u8>) -> Result<Result<Vec<u8>, pcsc::Error>, Error> {
+        // pcsc transmit blocks without a timeout, so wait for it on another thread
+        let card = Arc::clone(&self.card);
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let mut receive_buffer = vec![0; MAX_BUFFER_SIZE];
+            let rapdu = match card.lock().as_deref() {
+                Ok(Connection(Some(card))) => card
+                    .transmit(apdu.as_slice(), &mut receive_buffer)
+                    .map(|rapdu| rapdu.to_vec()),
+                Ok(Connection(None)) => Err(pcsc::Error::NoSmartcard),
+                Err(_) => Err(pcsc::Error::InternalError),
+            };
+            // the receiver is gone if the transmit timed out
+            let _ = sender.send(rapdu);
+        });
+        receiver
+            .recv_timeout(self.timeout)
+            .map_err(|_| Error::Timeout(format!("No response from card after {:?}", self.timeout)))
+    }
 
-    ctx.connect(reader, ShareMode::Shared, Protocols::ANY)?
-        .to_cktap()
+    /// Reconnect after a reset, the applet has to be selected again.
+    fn reconnect(&self) -> Result<(), Error> {
+        self.card
+            .lock()
+            .map_err(|e| Error::PcSc(e.to_string()))?
+            .0
+            .as_mut()
+            .ok_or_else(|| Error::PcSc("Card is disconnected".to_string()))?
+            .reconnect(ShareMode::Shared, Protocols::ANY, Disposition::LeaveCard)?;
+        let select = AppletSelect::default().apdu_bytes()?;
+        strip_status_word(self.transmit_once(select)??)?;
+        Ok(())
+    }
+}
+
+fn is_transient(e: &pcsc::Error) -> bool {
+    matches!(e, pcsc::Error::ResetCard | pcsc::Error::NotTransacted)
 }
 
-impl CkTransport for Card {
+fn is_replayable(apdu: &[u8]) -> bool {
+    if apdu.starts_with(&SELECT_CLA_INS_P1P2) {
+        return true;
+    }
+    let command: Option<Value> = apdu
+        .get(5..)
+        .and_then(|cbor| ciborium::de::from_reader(cbor).ok());
+    command
+        .and_then(|command| command.into_map().ok())
+        .and_then(|entries| {
+            entries
+                .into_iter()
+                .find(|(key, _)| key.as_text() == Some("cmd"))
+                .and_then(|(_, cmd)| cmd.into_text().ok())
+        })
+        .map_or(false, |cmd| REPLAYABLE_COMMANDS.contains(&cmd.as_str()))
+}
+
+impl CkTransport for PcscTransport {
     fn transmit_apdu(&self, apdu: Vec<u8>) -> Result<Vec<u8>, Error> {
-        let mut receive_buffer = vec![0; MAX_BUFFER_SIZE];
-        let rapdu = self.transmit(apdu.as_slice(), &mut receive_buffer)?;
-        Ok(rapdu.to_vec())
+        let mut attempt = 0;
+        loop {
+            match self.transmit_once(apdu.clone())? {
+                Ok(rapdu) => return strip_status_word(rapdu),
+                Err(e) if is_transient(&e) && attempt < self.retries => {
+                    thread::sleep(RETRY_BACKOFF * 2u32.pow(attempt as u32));
+                    attempt += 1;
+                    self.reconnect()?;
+                    if !is_replayable(&apdu) {
+                        return Err(Error::PcSc(format!(
+                            "{}, reconnected but the command is not safe to replay",
+                            e
+                        )));
+                    }
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apdu::{CertsCommand, ReadCommand, StatusCommand};
+
+    #[test]
+    fn test_is_replayable() {
+        assert!(is_replayable(
+            &AppletSelect::default().apdu_bytes().unwrap()
+        ));
+        assert!(is_replayable(
+            &StatusCommand::default().apdu_bytes().unwrap()
+        ));
+        assert!(is_replayable(
+            &CertsCommand::default().apdu_bytes().unwrap()
+        ));
+        // the read signature covers the card nonce, which changes with the reset
+        let read = ReadCommand::unauthenticated((0..16).collect()).unwrap();
+        assert!(!is_replayable(&read.apdu_bytes().unwrap()));
+        assert!(!is_replayable(&[]));
     }
 }
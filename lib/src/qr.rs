@@ -0,0 +1,76 @@
+//! QR codes for displaying a SATSCARD address or the card's NFC url, eg. on a kiosk screen.
+//!
+//! ```ignore
+//! use rust_cktap::qr;
+//!
+//! let address = satscard.address()?;
+//! println!("{}", qr::to_utf8(&qr::address_qr(&address)?));
+//! ```
+
+use crate::apdu::{Error, NfcUrl};
+use bitcoin::Address;
+use qrcode::render::{svg, unicode};
+use qrcode::types::QrError;
+
+pub use qrcode::QrCode;
+
+impl From<QrError> for Error {
+    fn from(e: QrError) -> Self {
+        Error::Qr(e.to_string())
+    }
+}
+
+/// QR code of a BIP21 `bitcoin:` uri for the address, uppercased when possible so it fits the
+/// denser alphanumeric mode.
+pub fn address_qr(address: &Address) -> Result<QrCode, Error> {
+    Ok(QrCode::new(address.to_qr_uri())?)
+}
+
+/// QR code of the url returned by the `nfc` command.
+pub fn nfc_url_qr(url: &NfcUrl) -> Result<QrCode, Error> {
+    Ok(QrCode::new(url.to_string())?)
+}
+
+/// Render with unicode half blocks, two modules per character, for printing to a terminal with a
+/// dark background.
+pub fn to_utf8(code: &QrCode) -> String {
+    code.render::<unicode::Dense1x2>()
+        .dark_color(unicode::Dense1x2::Light)
+        .light_color(unicode::Dense1x2::Dark)
+        .build()
+}
+
+/// Render as an SVG document.
+pub fn to_svg(code: &QrCode) -> String {
+    code.render::<svg::Color>().min_dimensions(200, 200).build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::Network;
+    use core::str::FromStr;
+
+    #[test]
+    fn test_address_qr() {
+        let address = Address::from_str("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq")
+            .unwrap()
+            .require_network(Network::Bitcoin)
+            .unwrap();
+        let code = address_qr(&address).unwrap();
+        let utf8 = to_utf8(&code);
+        // quiet zone on each side, two module rows per line
+        let lines = utf8.lines().count();
+        assert_eq!(lines, (code.width() + 8 + 1) / 2);
+        assert!(to_svg(&code).contains("<svg"));
+    }
+
+    #[test]
+    fn test_nfc_url_qr() {
+        let url = NfcUrl::parse(
+            "https://getsatscard.com/start#u=S&o=0&r=bcajrh2jdk&n=01fd4e1d42ec2b9e&s=ab",
+        )
+        .unwrap();
+        assert!(nfc_url_qr(&url).is_ok());
+    }
+}
@@ -0,0 +1,108 @@
+//! Transport wrapper that records every APDU exchange, for attaching to a bug report and replaying
+//! offline with `mock::MockTransport`.
+//!
+//! CVCs only appear encrypted as `xcvc` and secrets in responses are encrypted with the session
+//! key, which is not in the trace. The trace still reveals the card pubkey, addresses, xpubs,
+//! signed digests and signatures, so only share it with people you would show your wallet to.
+//!
+//! ```ignore
+//! use rust_cktap::recording::RecordingTransport;
+//!
+//! let card = RecordingTransport::new(transport).to_cktap()?;
+//! // ... run the failing flow ...
+//! card.transport.write_hex(std::fs::File::create("trace.txt")?)?;
+//! ```
+
+use crate::apdu::Error;
+use crate::commands::CkTransport;
+use hex::DisplayHex;
+use std::cell::RefCell;
+use std::io;
+
+pub struct RecordingTransport<T> {
+    inner: T,
+    exchanges: RefCell<Vec<(Vec<u8>, Vec<u8>)>>,
+}
+
+impl<T: CkTransport> RecordingTransport<T> {
+    pub fn new(inner: T) -> Self {
+        RecordingTransport {
+            inner,
+            exchanges: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// The (command, response) APDU pairs exchanged so far. Failed exchanges are not recorded.
+    pub fn exchanges(&self) -> Vec<(Vec<u8>, Vec<u8>)> {
+        self.exchanges.borrow().clone()
+    }
+
+    /// The response APDUs in order, to replay with `MockTransport::new`.
+    pub fn responses(&self) -> Vec<Vec<u8>> {
+        self.exchanges
+            .borrow()
+            .iter()
+            .map(|(_, response)| response.clone())
+            .collect()
+    }
+
+    /// Write the trace as hex, one `>> command` and `<< response` line per APDU.
+    pub fn write_hex<W: io::Write>(&self, mut out: W) -> io::Result<()> {
+        for (command, response) in self.exchanges.borrow().iter() {
+            writeln!(out, ">> {}", command.to_lower_hex_string())?;
+            writeln!(out, "<< {}", response.to_lower_hex_string())?;
+        }
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: CkTransport> CkTransport for RecordingTransport<T> {
+    fn transmit_apdu(&self, command_apdu: Vec<u8>) -> Result<Vec<u8>, Error> {
+        let response = self.inner.transmit_apdu(command_apdu.clone())?;
+        self.exchanges
+            .borrow_mut()
+            .push((command_apdu, response.clone()));
+        Ok(response)
+    }
+}
+
+#[cfg(all(test, feature = "test-utils"))]
+mod tests {
+    use super::*;
+    use crate::apdu::{AppletSelect, CommandApdu};
+    use crate::commands::SharedCommands;
+    use crate::mock::{expect_satscard, synthetic, MockTransport};
+
+    #[test]
+    fn test_record_and_replay() {
+        let transport = RecordingTransport::new(MockTransport::new(vec![
+            synthetic::SATSCARD_STATUS.to_vec(),
+            synthetic::SATSCARD_STATUS.to_vec(),
+        ]));
+        let mut card = transport.to_cktap().unwrap();
+        card.status().unwrap();
+        let recording = expect_satscard(card).transport;
+        let exchanges = recording.exchanges();
+        assert_eq!(exchanges.len(), 2);
+        assert_eq!(
+            exchanges[0].0,
+            AppletSelect::default().apdu_bytes().unwrap()
+        );
+
+        let mut hex = Vec::new();
+        recording.write_hex(&mut hex).unwrap();
+        let hex = String::from_utf8(hex).unwrap();
+        assert_eq!(hex.lines().count(), 4);
+        assert!(hex.starts_with(">> 00a40400"));
+
+        // the same flow runs offline from the recorded responses
+        let mut replayed = MockTransport::new(recording.responses())
+            .to_cktap()
+            .unwrap();
+        assert_eq!(replayed.status().unwrap().slots, Some((0, 10)));
+    }
+}
@@ -0,0 +1,56 @@
+//! Transport for browser wallets built with `wasm-bindgen`.
+//!
+//! The Web NFC `NDEFReader` API only reads and writes NDEF records, it has no access to the raw
+//! ISO-DEP channel the cktap protocol runs on. `WebNfcTransport` therefore gets its APDU exchange
+//! from a JS function supplied by the host page, for example a WebView bridge to the platform NFC
+//! stack. The function is called with the command APDU as a `Uint8Array` and must synchronously
+//! return the response APDU, also as a `Uint8Array`, or throw.
+//!
+//! ```ignore
+//! use rust_cktap::commands::CkTransport;
+//! use rust_cktap::web_nfc::WebNfcTransport;
+//! use rust_cktap::CkTapCard;
+//! use wasm_bindgen::prelude::*;
+//!
+//! // JS: `card_network((apdu) => bridge.transceive(apdu))`
+//! #[wasm_bindgen]
+//! pub fn card_network(transmit: js_sys::Function) -> Result<String, JsValue> {
+//!     let card: CkTapCard<_> = WebNfcTransport::new(transmit)
+//!         .to_cktap()
+//!         .map_err(|e| JsValue::from_str(&e.to_string()))?;
+//!     Ok(card.network().to_string())
+//! }
+//! ```
+
+use crate::apdu::Error;
+use crate::commands::CkTransport;
+use alloc::format;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use js_sys::{Function, Uint8Array};
+use wasm_bindgen::{JsCast, JsValue};
+
+pub struct WebNfcTransport {
+    transmit: Function,
+}
+
+impl WebNfcTransport {
+    /// Wrap a JS `(command: Uint8Array) => Uint8Array` APDU exchange function.
+    pub fn new(transmit: Function) -> Self {
+        WebNfcTransport { transmit }
+    }
+}
+
+impl CkTransport for WebNfcTransport {
+    fn transmit_apdu(&self, command_apdu: Vec<u8>) -> Result<Vec<u8>, Error> {
+        let command = Uint8Array::from(command_apdu.as_slice());
+        let response = self
+            .transmit
+            .call1(&JsValue::NULL, &command)
+            .map_err(|e| Error::WebNfc(format!("{:?}", e)))?;
+        let response = response
+            .dyn_into::<Uint8Array>()
+            .map_err(|_| Error::WebNfc("transmit did not return a Uint8Array".to_string()))?;
+        Ok(response.to_vec())
+    }
+}
@@ -0,0 +1,122 @@
+//! Regression vectors for the APDU framing, CVC encryption and CBOR encoding, to catch changes to
+//! the bytes this crate sends and parses. They are not the example sessions from the protocol
+//! docs and only show the encoding is stable, not that it matches a real card.
+//!
+//! The applet select APDU is the one given in the protocol docs. The other expected bytes were
+//! computed from the protocol's definitions with small fixed keys, so they can be recomputed by
+//! hand:
+//!
+//! - card private key 1, so the card pubkey is the generator `G`
+//! - ephemeral private key 2, so `epubkey` is `2G` and the session key is `sha256(2G)` of the
+//!   compressed point
+//! - `xcvc = cvc ^ (session_key ^ sha256(card_nonce || cmd))`
+#![cfg(feature = "std")]
+
+use rust_cktap::apdu::*;
+use rust_cktap::bitcoin::hex::FromHex;
+use rust_cktap::commands::calc_xcvc;
+use rust_cktap::secp256k1::{PublicKey, Secp256k1, SecretKey};
+
+const CARD_PUBKEY: &str = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+const EPUBKEY: &str = "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+const READ_XCVC: &str = "d33c88cff37a";
+
+fn bytes(hex: &str) -> Vec<u8> {
+    Vec::from_hex(hex).unwrap()
+}
+
+fn eprivkey() -> SecretKey {
+    let mut two = [0u8; 32];
+    two[31] = 2;
+    SecretKey::from_slice(&two).unwrap()
+}
+
+// card nonce 00 01 .. 0f
+fn card_nonce() -> Vec<u8> {
+    (0..16).collect()
+}
+
+#[test]
+fn test_applet_select() {
+    assert_eq!(
+        AppletSelect::default().apdu_bytes().unwrap(),
+        bytes("00a404000ff0436f696e6b697465434152447631")
+    );
+}
+
+#[test]
+fn test_status_command() {
+    // {"cmd": "status"}
+    assert_eq!(
+        StatusCommand::default().apdu_bytes().unwrap(),
+        bytes("00cb00000ca163636d6466737461747573")
+    );
+}
+
+#[test]
+fn test_xcvc() {
+    let card_pubkey = PublicKey::from_slice(&bytes(CARD_PUBKEY)).unwrap();
+    let (epubkey, xcvc) = calc_xcvc(
+        &Secp256k1::new(),
+        "read",
+        &card_nonce(),
+        &card_pubkey,
+        &eprivkey(),
+        "123456",
+    );
+    assert_eq!(epubkey.serialize().to_vec(), bytes(EPUBKEY));
+    assert_eq!(xcvc, bytes(READ_XCVC));
+}
+
+#[test]
+fn test_read_command() {
+    let mut nonce = vec![0xaa; 15];
+    nonce.push(0x01);
+    let epubkey = PublicKey::from_slice(&bytes(EPUBKEY)).unwrap();
+    let command = ReadCommand::authenticated(nonce, epubkey, bytes(READ_XCVC)).unwrap();
+    // {"cmd": "read", "nonce": h'aa..01', "epubkey": h'02c6..', "xcvc": h'd33c..'}
+    assert_eq!(
+        command.apdu_bytes().unwrap(),
+        bytes(concat!(
+            "00cb000058",
+            "a463636d646472656164",
+            "656e6f6e636550aaaaaaaaaaaaaaaaaaaaaaaaaaaaaa01",
+            "67657075626b65795821",
+            "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5",
+            "647863766346d33c88cff37a",
+        ))
+    );
+}
+
+#[test]
+fn test_read_response() {
+    // {"sig": h'11..11', "pubkey": G, "card_nonce": h'22..22'}
+    let cbor = bytes(concat!(
+        "a3637369675840",
+        "11111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111",
+        "667075626b65795821",
+        "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+        "6a636172645f6e6f6e636550",
+        "22222222222222222222222222222222",
+    ));
+    let response = ReadResponse::from_cbor(cbor).unwrap();
+    assert_eq!(response.sig, vec![0x11; 64]);
+    assert_eq!(response.card_nonce, vec![0x22; 16]);
+    assert_eq!(
+        response.pubkey(None).unwrap().serialize().to_vec(),
+        bytes(CARD_PUBKEY)
+    );
+}
+
+#[test]
+fn test_error_response() {
+    // {"error": "Bad auth", "code": 401}
+    let cbor = bytes("a2656572726f7268426164206175746864636f6465190191");
+    match ReadResponse::from_cbor(cbor) {
+        Err(Error::CkTap { error, code }) => {
+            assert_eq!(error, "Bad auth");
+            assert_eq!(code, 401);
+        }
+        result => panic!("unexpected result {:?}", result),
+    }
+}